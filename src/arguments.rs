@@ -1,33 +1,398 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use getset::{CopyGetters, Getters};
 
+use crate::{
+    bootstrap::ResamplingMethod,
+    data::{StabilityAggregation, StabilityDirection},
+    formula::FormulaModel,
+    genealogy::SelectionStrategy,
+    metric::{StabilityMetric, StabilityMetricType},
+    optimisation::{
+        FitMethod, InformationCriterion, LossFunction, ModelSpecification, ObservationWeighting,
+        RegressionModelType, RegressionSettings, DEFAULT_GRADIENT_TOLERANCE, DEFAULT_LOESS_SPAN,
+        DEFAULT_MAXIMUM_ITERATIONS, DEFAULT_STARTS, DEFAULT_STEP_TOLERANCE,
+    },
+};
+
 /// A tool for optimising the resolution parameter of the Leiden clustering algorithm.
 #[derive(Parser, CopyGetters, Getters, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 pub struct CommandLineArguments {
+    /// A command to run instead of the optimisation.
+    #[getset(get = "pub")]
+    #[command(subcommand)]
+    command: Option<Command>,
     /// The path to the CSV file (UTF-8 encoded, comma delimeted) containing the clustering information.
     #[getset(get = "pub")]
-    csv_file: PathBuf,
+    #[arg(required = true)]
+    csv_file: Option<PathBuf>,
     /// The output directory [default: the parent directory of the input CSV]
     #[arg(short, long)]
     output_directory: Option<PathBuf>,
+    /// The strategy used to select the optimal clustering from the top branch.
+    #[getset(get_copy = "pub")]
+    #[arg(long, value_enum, default_value_t = SelectionStrategy::Threshold)]
+    selection_strategy: SelectionStrategy,
     /// The threashold used to compute the optimal clustering resolution.
     #[getset(get_copy = "pub")]
     #[arg(short, long, default_value_t = 0.95)]
     stability_threashold: f64,
+    /// The metric used to score the stability of transitions between clusterings.
+    #[getset(get_copy = "pub")]
+    #[arg(short = 'm', long, value_enum, default_value_t = StabilityMetricType::SquaredOverlap)]
+    stability_metric: StabilityMetricType,
+    /// The direction in which the overlap of parent and child clusters is scored.
+    /// Only applies to the squared overlap metric [default: child-to-parent]
+    #[arg(short, long, value_enum)]
+    direction: Option<StabilityDirection>,
+    /// The method used to aggregate the stabilities of individual clusters.
+    /// Only applies to the squared overlap metric [default: mean]
+    #[arg(short, long, value_enum)]
+    aggregation: Option<AggregationMethod>,
+    /// The proportion of the least and the most stable clusters each,
+    /// that is removed before averaging if the trimmed mean aggregation is used.
+    #[arg(long, default_value_t = 0.1, value_parser = parse_trim_proportion)]
+    trim_proportion: f64,
+    /// The method used to fit a curve to the stabilities of the top branch.
+    #[getset(get_copy = "pub")]
+    #[arg(short, long, value_enum, default_value_t = FitMethod::Regression)]
+    fit_method: FitMethod,
+    /// The curve family fitted to the stabilities of the top branch by a regression.
+    #[arg(short, long, value_enum, default_value_t = RegressionModelType::Hyperbola)]
+    regression_model: RegressionModelType,
+    /// A formula in the number of clusters `x` fitted instead of the regression model,
+    /// such as `a*exp(-b*x) + c`. It supports `+`, `-`, `*`, `/`, `^`, parentheses and
+    /// the functions `exp`, `ln` and `sqrt`.
+    #[arg(long, conflicts_with = "model_selection", requires = "starting_values")]
+    formula: Option<String>,
+    /// The starting values of all formula parameters as comma-separated `name=value` pairs.
+    #[arg(long, value_delimiter = ',', value_parser = parse_starting_value, requires = "formula")]
+    starting_values: Vec<(String, f64)>,
+    /// The loss function minimised by the regression, where robust losses limit the
+    /// influence of outlier transitions.
+    #[arg(short, long, value_enum, default_value_t = LossFunction::SquaredError)]
+    loss: LossFunction,
+    /// The weighting of transitions in the regression.
+    #[getset(get_copy = "pub")]
+    #[arg(short, long, value_enum, default_value_t = ObservationWeighting::Uniform)]
+    weighting: ObservationWeighting,
+    /// The fraction of observations used for each local fit if the LOESS fit method is used.
+    #[arg(long, default_value_t = DEFAULT_LOESS_SPAN, value_parser = parse_span)]
+    loess_span: f64,
+    /// The maximum number of iterations of the regression optimiser.
+    #[arg(long, default_value_t = DEFAULT_MAXIMUM_ITERATIONS)]
+    maximum_iterations: usize,
+    /// The tolerance of the gradient of the squared residuals, at which the regression
+    /// optimiser stops.
+    #[arg(long, default_value_t = DEFAULT_GRADIENT_TOLERANCE, value_parser = parse_non_negative)]
+    gradient_tolerance: f64,
+    /// The tolerance of the relative change of parameters, at which the regression
+    /// optimiser stops.
+    #[arg(long, default_value_t = DEFAULT_STEP_TOLERANCE, value_parser = parse_non_negative)]
+    step_tolerance: f64,
+    /// The number of data-derived starting points the regression optimiser is run from.
+    /// The best fit without a singularity in the observed range of clusters is selected.
+    #[arg(long, default_value_t = DEFAULT_STARTS, value_parser = parse_starts)]
+    starts: usize,
+    /// Fits all regression models and selects the best supported one according to the
+    /// specified information criterion. Only applies to the regression fit method
+    /// with the squared error loss [default: fit the specified regression model]
+    #[getset(get_copy = "pub")]
+    #[arg(long, value_enum)]
+    model_selection: Option<InformationCriterion>,
+    /// Detects abrupt changes of the stability along the top branch as candidate numbers
+    /// of clusters.
+    #[getset(get_copy = "pub")]
+    #[arg(long)]
+    change_points: bool,
+    /// The penalty per change point [default: estimated from the noise of the stabilities]
+    #[getset(get_copy = "pub")]
+    #[arg(long, value_parser = parse_non_negative)]
+    change_point_penalty: Option<f64>,
+    /// Ranks the specified number of branches with the highest total stability and reports
+    /// where they diverge from the top branch.
+    #[getset(get_copy = "pub")]
+    #[arg(long, value_parser = parse_number_of_branches)]
+    top_branches: Option<usize>,
+    /// Computes the co-clustering consensus over all resolutions.
+    #[getset(get_copy = "pub")]
+    #[arg(long)]
+    consensus: bool,
+    /// The minimal fraction of resolutions in which cells must be clustered together
+    /// to be joined into the same consensus cluster.
+    #[getset(get_copy = "pub")]
+    #[arg(long, default_value_t = 0.8, value_parser = parse_fraction)]
+    consensus_cutoff: f64,
+    /// The number of permutations used to test the stability of the top branch transitions
+    /// against a null model [default: no test]
+    #[getset(get_copy = "pub")]
+    #[arg(long, default_value_t = 0, hide_default_value = true)]
+    permutations: usize,
+    /// The number of bootstrap replicates used to estimate the distribution of the
+    /// optimal number of clusters [default: no bootstrap]
+    #[getset(get_copy = "pub")]
+    #[arg(long, default_value_t = 0, hide_default_value = true)]
+    bootstrap_replicates: usize,
+    /// The fraction of cells drawn without replacement for each bootstrap replicate
+    /// [default: draw all cells with replacement]
+    #[arg(long, value_parser = parse_fraction)]
+    subsample_fraction: Option<f64>,
+    /// The neighbour graph the clustering was performed on as edge list or Matrix Market
+    /// file (.mtx), where cells are identified by their 1-based column index in the CSV file.
+    /// If specified, the graph-based quality of all clusterings is computed.
+    #[getset(get = "pub")]
+    #[arg(long)]
+    neighbour_graph: Option<PathBuf>,
+    /// The resolution parameter of the constant Potts model (CPM) used for the graph quality
+    /// [default: the resolution of each clustering, which is only meaningful if the Leiden
+    /// algorithm optimised the CPM]
+    #[getset(get_copy = "pub")]
+    #[arg(long, value_parser = parse_non_negative, requires = "neighbour_graph")]
+    cpm_resolution: Option<f64>,
+    /// A fit saved by a previous run (regression_*.json), which is plotted as reference curve.
+    #[getset(get = "pub")]
+    #[arg(long)]
+    reference_fit: Option<PathBuf>,
+    /// The seed of the random number generator.
+    #[getset(get_copy = "pub")]
+    #[arg(long, default_value_t = 42)]
+    seed: u64,
+}
+
+/// The commands that can be run instead of the optimisation.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Predicts the stability for numbers of clusters from a fit saved by a previous run.
+    Predict(PredictArguments),
+}
+
+/// The arguments of the predict command.
+#[derive(Args, Getters, Debug)]
+pub struct PredictArguments {
+    /// The fit saved by a previous run (regression_*.json).
+    #[getset(get = "pub")]
+    fit_file: PathBuf,
+    /// The numbers of clusters to predict the stability for.
+    #[getset(get = "pub")]
+    #[arg(required = true)]
+    clusters: Vec<f64>,
+    /// The CSV file to write the predictions to [default: the standard output]
+    #[getset(get = "pub")]
+    #[arg(short, long)]
+    output_file: Option<PathBuf>,
+}
+
+/// The methods to aggregate the stabilities of individual child clusters.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AggregationMethod {
+    /// The unweighted mean.
+    Mean,
+    /// The mean weighted by the number of cells per cluster.
+    CellWeightedMean,
+    /// The median.
+    Median,
+    /// The mean after removing the least and the most stable clusters.
+    TrimmedMean,
 }
 
 impl CommandLineArguments {
     /// Returns the output directory.
     /// If no directory has been specified the parent directory of the input file is returned.
     pub fn output_directory(&self) -> PathBuf {
-        self.output_directory.as_ref().map(|output_dir| output_dir.to_path_buf()).unwrap_or_else(|| self.csv_file_parent_directory())
+        self.output_directory
+            .as_ref()
+            .map(|output_dir| output_dir.to_path_buf())
+            .unwrap_or_else(|| self.csv_file_parent_directory())
+    }
+
+    /// Returns the metric used to score the stability of transitions between clusterings.
+    /// Returns an error if the direction or aggregation is specified for a metric
+    /// that does not score individual clusters or if the weighting requires
+    /// the stabilities of individual clusters.
+    pub fn metric(&self) -> Result<Box<dyn StabilityMetric>, String> {
+        if self.stability_metric == StabilityMetricType::VariationOfInformation {
+            if self.direction.is_some() || self.aggregation.is_some() {
+                return Err("The direction and aggregation only apply to the squared overlap \
+                            metric."
+                    .to_string());
+            }
+            if self.weighting == ObservationWeighting::InverseVariance {
+                return Err("The inverse variance weighting requires the squared overlap metric, \
+                            as it is based on the stabilities of individual clusters."
+                    .to_string());
+            }
+        }
+        Ok(self
+            .stability_metric
+            .metric(self.direction(), self.stability_aggregation()))
+    }
+
+    /// Returns the direction in which the overlap of parent and child clusters is scored.
+    pub fn direction(&self) -> StabilityDirection {
+        self.direction.unwrap_or(StabilityDirection::ChildToParent)
+    }
+
+    /// Returns the method used to aggregate the stabilities of individual clusters.
+    pub fn stability_aggregation(&self) -> StabilityAggregation {
+        match self.aggregation.unwrap_or(AggregationMethod::Mean) {
+            AggregationMethod::Mean => StabilityAggregation::Mean,
+            AggregationMethod::CellWeightedMean => StabilityAggregation::CellWeightedMean,
+            AggregationMethod::Median => StabilityAggregation::Median,
+            AggregationMethod::TrimmedMean => {
+                StabilityAggregation::TrimmedMean(self.trim_proportion)
+            },
+        }
+    }
+
+    /// Returns the model fitted by a regression, which is the formula if one is specified.
+    /// Returns an error if the formula or its starting values are invalid.
+    pub fn model_specification(&self) -> Result<ModelSpecification, String> {
+        match &self.formula {
+            Some(formula) => Ok(ModelSpecification::Formula(
+                FormulaModel::parse(formula)?.with_starting_values(&self.starting_values)?,
+            )),
+            None => Ok(ModelSpecification::Named(self.regression_model)),
+        }
+    }
+
+    /// Returns the settings used to fit regression models.
+    pub fn regression_settings(&self) -> RegressionSettings {
+        RegressionSettings::new(
+            self.loss,
+            self.loess_span,
+            self.maximum_iterations,
+            self.gradient_tolerance,
+            self.step_tolerance,
+            self.starts,
+        )
+    }
+
+    /// Returns the method used to resample cells for bootstrap replicates.
+    pub fn resampling_method(&self) -> ResamplingMethod {
+        self.subsample_fraction
+            .map(ResamplingMethod::Subsample)
+            .unwrap_or(ResamplingMethod::WithReplacement)
     }
 
     /// Returns the directory that contains the input CSV file.
     fn csv_file_parent_directory(&self) -> PathBuf {
-        self.csv_file.parent().map(|parent| parent.to_path_buf()).unwrap_or("/".into())
+        self.csv_file
+            .as_deref()
+            .and_then(|csv_file| csv_file.parent())
+            .map(|parent| parent.to_path_buf())
+            .unwrap_or("/".into())
+    }
+}
+
+/// Parses the proportion of values to trim from each end, which must be in the interval `[0, 0.5)`.
+///
+/// # Parameters
+///
+/// * `value` - the command line value to parse
+fn parse_trim_proportion(value: &str) -> Result<f64, String> {
+    let proportion: f64 = value.parse().map_err(|error| format!("{}", error))?;
+    if (0.0..0.5).contains(&proportion) {
+        Ok(proportion)
+    } else {
+        Err(format!("The proportion {} is not in the interval [0, 0.5).", proportion))
+    }
+}
+
+/// Parses the span of a local regression, which must be positive.
+///
+/// # Parameters
+///
+/// * `value` - the command line value to parse
+fn parse_span(value: &str) -> Result<f64, String> {
+    let span: f64 = value.parse().map_err(|error| format!("{}", error))?;
+    if span > 0.0 && span.is_finite() {
+        Ok(span)
+    } else {
+        Err(format!("The span {} is not positive.", span))
+    }
+}
+
+/// Parses the number of starting points, which must be positive.
+///
+/// # Parameters
+///
+/// * `value` - the command line value to parse
+fn parse_starts(value: &str) -> Result<usize, String> {
+    let starts: usize = value.parse().map_err(|error| format!("{}", error))?;
+    if starts > 0 {
+        Ok(starts)
+    } else {
+        Err("At least one starting point is required.".to_string())
+    }
+}
+
+/// Parses the number of branches to rank, which must be positive.
+///
+/// # Parameters
+///
+/// * `value` - the command line value to parse
+fn parse_number_of_branches(value: &str) -> Result<usize, String> {
+    let branches: usize = value.parse().map_err(|error| format!("{}", error))?;
+    if branches > 0 {
+        Ok(branches)
+    } else {
+        Err("At least one branch must be ranked.".to_string())
+    }
+}
+
+/// Parses the starting value of a formula parameter given as `name=value`.
+///
+/// # Parameters
+///
+/// * `value` - the command line value to parse
+fn parse_starting_value(value: &str) -> Result<(String, f64), String> {
+    let (name, number) = value
+        .split_once('=')
+        .ok_or_else(|| format!("The starting value {} is not of the form name=value.", value))?;
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|error| format!("{}", error))?;
+    if number.is_finite() {
+        Ok((name.trim().to_string(), number))
+    } else {
+        Err(format!("The starting value {} is not finite.", number))
+    }
+}
+
+/// Parses a fraction, which must be in the interval `(0, 1]`.
+///
+/// # Parameters
+///
+/// * `value` - the command line value to parse
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    let fraction: f64 = value.parse().map_err(|error| format!("{}", error))?;
+    if fraction > 0.0 && fraction <= 1.0 {
+        Ok(fraction)
+    } else {
+        Err(format!("The fraction {} is not in the interval (0, 1].", fraction))
+    }
+}
+
+/// Parses a finite value, which must not be negative.
+///
+/// # Parameters
+///
+/// * `value` - the command line value to parse
+fn parse_non_negative(value: &str) -> Result<f64, String> {
+    let number: f64 = value.parse().map_err(|error| format!("{}", error))?;
+    if number >= 0.0 && number.is_finite() {
+        Ok(number)
+    } else {
+        Err(format!("The value {} is not a finite non-negative number.", number))
     }
 }
@@ -0,0 +1,424 @@
+//! This module provides a cell resampling bootstrap of the optimal number of clusters.
+//!
+//! For each replicate the cells are resampled, the cluster stability graph is rebuilt and
+//! the optimal clustering is selected from its top branch.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    rc::Rc,
+};
+
+use getset::{CopyGetters, Getters};
+use rand::{seq::index::sample, Rng};
+use serde::Serialize;
+
+use crate::{
+    data::{CellSample, ResolutionData},
+    graph::{to_graph, top_branch, ResolutionNode},
+    metric::StabilityMetric,
+};
+
+/// The methods to resample cells.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResamplingMethod {
+    /// Draws as many cells as present with replacement.
+    WithReplacement,
+    /// Draws the specified fraction of cells without replacement.
+    Subsample(f64),
+}
+
+#[derive(CopyGetters, Clone, Debug, PartialEq, Serialize)]
+/// The optimal clustering of a single bootstrap replicate.
+pub struct BootstrapReplicate {
+    /// The index of the replicate.
+    #[getset(get_copy = "pub")]
+    replicate: usize,
+    /// The optimal number of clusters or `None` if no clustering passed the trimming.
+    #[getset(get_copy = "pub")]
+    optimal_clusters: Option<usize>,
+    /// The optimal resolution or `None` if no clustering passed the trimming.
+    #[getset(get_copy = "pub")]
+    optimal_resolution: Option<f64>,
+}
+
+#[derive(CopyGetters, Clone, Debug, PartialEq, Serialize)]
+/// How often a resolution was chosen as optimal.
+pub struct ResolutionFrequency {
+    /// The resolution.
+    #[getset(get_copy = "pub")]
+    resolution: f64,
+    /// The number of replicates the resolution was chosen in.
+    #[getset(get_copy = "pub")]
+    count: usize,
+    /// The fraction of replicates the resolution was chosen in.
+    #[getset(get_copy = "pub")]
+    frequency: f64,
+}
+
+#[derive(CopyGetters, Clone, Debug, PartialEq, Serialize)]
+/// How often a number of clusters was chosen as optimal.
+pub struct ClusterCountFrequency {
+    /// The number of clusters.
+    #[getset(get_copy = "pub")]
+    clusters: usize,
+    /// The number of replicates the number of clusters was chosen in.
+    #[getset(get_copy = "pub")]
+    count: usize,
+    /// The fraction of replicates the number of clusters was chosen in.
+    #[getset(get_copy = "pub")]
+    frequency: f64,
+}
+
+#[derive(CopyGetters, Getters, Clone, Debug, PartialEq, Serialize)]
+/// The distribution of optimal clusterings over all bootstrap replicates.
+pub struct BootstrapSummary {
+    /// The mean optimal number of clusters or `None` if no replicate passed the trimming.
+    #[getset(get_copy = "pub")]
+    mean_optimal_clusters: Option<f64>,
+    /// The standard deviation of the optimal number of clusters or `None` if no replicate
+    /// passed the trimming.
+    #[getset(get_copy = "pub")]
+    standard_deviation_optimal_clusters: Option<f64>,
+    /// How often each number of clusters was chosen ordered by the number of clusters.
+    #[getset(get = "pub")]
+    cluster_count_frequencies: Vec<ClusterCountFrequency>,
+    /// How often each resolution was chosen ordered by resolution.
+    #[getset(get = "pub")]
+    resolution_frequencies: Vec<ResolutionFrequency>,
+    /// The optimal clustering of each replicate.
+    #[getset(get = "pub")]
+    replicates: Vec<BootstrapReplicate>,
+}
+
+/// Returns the distribution of optimal clusterings over the specified number of replicates of
+/// resampled cells.
+/// Returns an error if the optimal node of any replicate cannot be selected.
+///
+/// # Parameters
+///
+/// * `resolutions` - the clusterings sampled at different resolutions
+/// * `metric` - the metric used to score the stability of parent-child-transitions
+/// * `optimal_node` - selects the optimal node of a top branch built from the specified
+///   clusterings or returns `None` if no node is acceptable
+/// * `method` - the method used to resample cells
+/// * `replicates` - the number of bootstrap replicates
+/// * `rng` - the random number generator used to resample cells
+pub fn bootstrap<M, F, R>(
+    resolutions: &[ResolutionData],
+    metric: &M,
+    optimal_node: F,
+    method: ResamplingMethod,
+    replicates: usize,
+    rng: &mut R,
+) -> Result<BootstrapSummary, String>
+where
+    M: StabilityMetric + ?Sized,
+    F: Fn(&[Rc<ResolutionNode>], &[ResolutionData]) -> Result<Option<Rc<ResolutionNode>>, String>,
+    R: Rng + ?Sized,
+{
+    let cell_clusters = cell_cluster_maps(resolutions);
+    let cells: Vec<usize> = resolutions
+        .first()
+        .map(ResolutionData::sorted_cells)
+        .unwrap_or_default();
+    let replicates: Vec<BootstrapReplicate> = (0..replicates)
+        .map(|replicate| {
+            let sampled_cells = resample_cells(&cells, method, rng);
+            let resampled: Vec<ResolutionData> = resolutions
+                .iter()
+                .zip(&cell_clusters)
+                .map(|(resolution, clusters)| {
+                    let cell_samples: Vec<CellSample> = sampled_cells
+                        .iter()
+                        .enumerate()
+                        .map(|(new_id, cell)| CellSample::new(new_id, clusters[cell]))
+                        .collect();
+                    ResolutionData::new(resolution.resolution(), &cell_samples)
+                })
+                .collect();
+            let optimal = optimal_node(&top_branch(&to_graph(&resampled, metric)), &resampled)?;
+            Ok(BootstrapReplicate {
+                replicate,
+                optimal_clusters: optimal.as_ref().map(|node| node.number_of_clusters()),
+                optimal_resolution: optimal.as_ref().map(|node| node.resolution()),
+            })
+        })
+        .collect::<Result<Vec<BootstrapReplicate>, String>>()?;
+    Ok(summarise(replicates))
+}
+
+/// Returns a map of cell IDs to cluster IDs for each clustering.
+///
+/// # Parameters
+///
+/// * `resolutions` - the clusterings sampled at different resolutions
+fn cell_cluster_maps(resolutions: &[ResolutionData]) -> Vec<HashMap<usize, usize>> {
+    resolutions
+        .iter()
+        .map(|resolution| {
+            resolution
+                .clustered_cells()
+                .iter()
+                .flat_map(|cluster| {
+                    cluster
+                        .cells()
+                        .iter()
+                        .map(move |cell| (*cell, cluster.cluster_id()))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Returns the resampled cells.
+///
+/// # Parameters
+///
+/// * `cells` - the cells to resample
+/// * `method` - the method used to resample cells
+/// * `rng` - the random number generator used to resample cells
+fn resample_cells<R: Rng + ?Sized>(
+    cells: &[usize],
+    method: ResamplingMethod,
+    rng: &mut R,
+) -> Vec<usize> {
+    if cells.is_empty() {
+        return Vec::new();
+    }
+    match method {
+        ResamplingMethod::WithReplacement => (0..cells.len())
+            .map(|_| cells[rng.gen_range(0..cells.len())])
+            .collect(),
+        ResamplingMethod::Subsample(fraction) => {
+            let amount = ((cells.len() as f64 * fraction).round() as usize).clamp(1, cells.len());
+            let mut indices = sample(rng, cells.len(), amount).into_vec();
+            indices.sort();
+            indices.into_iter().map(|index| cells[index]).collect()
+        },
+    }
+}
+
+/// Summarises the optimal clusterings of all replicates.
+///
+/// # Parameters
+///
+/// * `replicates` - the optimal clusterings of all replicates
+fn summarise(replicates: Vec<BootstrapReplicate>) -> BootstrapSummary {
+    let total = replicates.len() as f64;
+    let optimal_clusters: Vec<f64> = replicates
+        .iter()
+        .filter_map(|replicate| replicate.optimal_clusters())
+        .map(|clusters| clusters as f64)
+        .collect();
+    let (mean_optimal_clusters, standard_deviation_optimal_clusters) =
+        if optimal_clusters.is_empty() {
+            (None, None)
+        } else {
+            let mean = optimal_clusters.iter().sum::<f64>() / optimal_clusters.len() as f64;
+            let variance = optimal_clusters
+                .iter()
+                .map(|clusters| (clusters - mean).powi(2))
+                .sum::<f64>()
+                / optimal_clusters.len() as f64;
+            (Some(mean), Some(variance.sqrt()))
+        };
+    let mut cluster_counts: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut resolution_counts: Vec<(f64, usize)> = Vec::new();
+    for replicate in &replicates {
+        if let Some(clusters) = replicate.optimal_clusters() {
+            *cluster_counts.entry(clusters).or_insert(0) += 1;
+        }
+        if let Some(resolution) = replicate.optimal_resolution() {
+            match resolution_counts
+                .iter_mut()
+                .find(|(known, _)| *known == resolution)
+            {
+                Some((_, count)) => *count += 1,
+                None => resolution_counts.push((resolution, 1)),
+            }
+        }
+    }
+    resolution_counts.sort_by(|a, b| {
+        a.0.partial_cmp(&b.0)
+            .expect("Resolutions must be valid numbers.")
+    });
+    BootstrapSummary {
+        mean_optimal_clusters,
+        standard_deviation_optimal_clusters,
+        cluster_count_frequencies: cluster_counts
+            .into_iter()
+            .map(|(clusters, count)| ClusterCountFrequency {
+                clusters,
+                count,
+                frequency: count as f64 / total,
+            })
+            .collect(),
+        resolution_frequencies: resolution_counts
+            .into_iter()
+            .map(|(resolution, count)| ResolutionFrequency {
+                resolution,
+                count,
+                frequency: count as f64 / total,
+            })
+            .collect(),
+        replicates,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_ulps_eq;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::{
+        genealogy::{select_clustering, trim_branch, SelectionStrategy},
+        metric::SquaredOverlapMetric,
+        optimisation::{
+            FitMethod, ModelSpecification, ObservationWeighting, RegressionModelType,
+            RegressionSettings,
+        },
+    };
+
+    /// Returns clusterings of 60 cells into consecutive blocks of equal size.
+    fn block_clusterings() -> Vec<ResolutionData> {
+        [1, 2, 3, 4, 5, 6, 10, 12, 15, 20]
+            .into_iter()
+            .map(|clusters| {
+                let cells: Vec<CellSample> = (0..60)
+                    .map(|cell_id| CellSample::new(cell_id, cell_id * clusters / 60))
+                    .collect();
+                ResolutionData::new(clusters as f64 / 10.0, &cells)
+            })
+            .collect()
+    }
+
+    /// Returns the finest clustering remaining after trimming the branch at the threshold
+    /// crossing of the fitted stabilities.
+    ///
+    /// # Parameters
+    ///
+    /// * `branch` - the top branch
+    /// * `resolutions` - the clusterings the branch has been built from
+    fn trimmed_optimal_node(
+        branch: &[Rc<ResolutionNode>],
+        resolutions: &[ResolutionData],
+    ) -> Result<Option<Rc<ResolutionNode>>, String> {
+        let regression = FitMethod::Regression.fit(
+            &ObservationWeighting::Uniform.observations(branch, resolutions)?,
+            &ModelSpecification::Named(RegressionModelType::ExponentialDecay),
+            &RegressionSettings::default(),
+        );
+        let selection = select_clustering(branch, &regression, SelectionStrategy::Threshold, 0.8);
+        Ok(trim_branch(branch, &selection).pop())
+    }
+
+    #[test]
+    fn test_bootstrap() {
+        let resolutions = block_clusterings();
+        let metric = SquaredOverlapMetric::default();
+        let expected =
+            trimmed_optimal_node(&top_branch(&to_graph(&resolutions, &metric)), &resolutions)
+                .unwrap()
+                .unwrap();
+        // The trimming removes the finer clusterings of the top branch.
+        assert!(expected.number_of_clusters() < 20);
+        // Subsampling all cells reproduces the original clusterings in every replicate.
+        let summary = bootstrap(
+            &resolutions,
+            &metric,
+            trimmed_optimal_node,
+            ResamplingMethod::Subsample(1.0),
+            5,
+            &mut ChaCha8Rng::seed_from_u64(42),
+        )
+        .unwrap();
+        assert_eq!(summary.replicates().len(), 5);
+        for (index, replicate) in summary.replicates().iter().enumerate() {
+            assert_eq!(replicate.replicate(), index);
+            assert_eq!(replicate.optimal_clusters(), Some(expected.number_of_clusters()));
+            assert_eq!(replicate.optimal_resolution(), Some(expected.resolution()));
+        }
+        assert_ulps_eq!(
+            summary.mean_optimal_clusters().unwrap(),
+            expected.number_of_clusters() as f64
+        );
+        assert_ulps_eq!(summary.standard_deviation_optimal_clusters().unwrap(), 0.0);
+        // Resampling with replacement is reproducible with the same seed.
+        let resample = |seed| {
+            bootstrap(
+                &resolutions,
+                &metric,
+                trimmed_optimal_node,
+                ResamplingMethod::WithReplacement,
+                10,
+                &mut ChaCha8Rng::seed_from_u64(seed),
+            )
+            .unwrap()
+        };
+        let summary = resample(7);
+        assert_eq!(summary, resample(7));
+        let count: usize = summary
+            .cluster_count_frequencies()
+            .iter()
+            .map(|frequency| frequency.count())
+            .sum();
+        assert_eq!(
+            count,
+            summary
+                .replicates()
+                .iter()
+                .filter(|replicate| replicate.optimal_clusters().is_some())
+                .count()
+        );
+    }
+
+    #[test]
+    fn test_resample_cells() {
+        let cells: Vec<usize> = (10..30).collect();
+        let mut rng = ChaCha8Rng::seed_from_u64(42);
+        let with_replacement = resample_cells(&cells, ResamplingMethod::WithReplacement, &mut rng);
+        assert_eq!(with_replacement.len(), cells.len());
+        assert!(with_replacement.iter().all(|cell| cells.contains(cell)));
+        let mut subsample = resample_cells(&cells, ResamplingMethod::Subsample(0.5), &mut rng);
+        assert_eq!(subsample.len(), 10);
+        subsample.dedup();
+        assert_eq!(subsample.len(), 10);
+        assert!(resample_cells(&[], ResamplingMethod::WithReplacement, &mut rng).is_empty());
+    }
+
+    #[test]
+    fn test_summarise() {
+        let replicates = vec![
+            BootstrapReplicate {
+                replicate: 0,
+                optimal_clusters: Some(4),
+                optimal_resolution: Some(0.4),
+            },
+            BootstrapReplicate {
+                replicate: 1,
+                optimal_clusters: Some(6),
+                optimal_resolution: Some(0.6),
+            },
+            BootstrapReplicate {
+                replicate: 2,
+                optimal_clusters: Some(4),
+                optimal_resolution: Some(0.4),
+            },
+            BootstrapReplicate {
+                replicate: 3,
+                optimal_clusters: None,
+                optimal_resolution: None,
+            },
+        ];
+        let summary = summarise(replicates);
+        assert_ulps_eq!(summary.mean_optimal_clusters().unwrap(), 14.0 / 3.0);
+        assert_eq!(summary.cluster_count_frequencies().len(), 2);
+        assert_eq!(summary.cluster_count_frequencies()[0].clusters(), 4);
+        assert_eq!(summary.cluster_count_frequencies()[0].count(), 2);
+        assert_ulps_eq!(summary.resolution_frequencies()[0].frequency(), 0.5);
+        assert_ulps_eq!(summary.resolution_frequencies()[1].resolution(), 0.6);
+        assert_eq!(summary.replicates().len(), 4);
+    }
+}
@@ -0,0 +1,233 @@
+//! This module ranks the branches of a cluster stability graph.
+//!
+//! Every resolution of a layer can be reached from every resolution of the previous layer,
+//! so the graph contains many root-to-leaf branches besides the optimal one. The k best
+//! branches are enumerated by keeping the k best partial branches ending at every node
+//! while the graph is traversed layer by layer.
+
+use std::rc::Rc;
+
+use getset::{CopyGetters, Getters};
+use serde::Serialize;
+
+use crate::{
+    data::ResolutionData,
+    graph::{keep_best, to_k_best_graph, ResolutionNode},
+    metric::StabilityMetric,
+};
+
+#[derive(CopyGetters, Clone, Debug, PartialEq, Serialize)]
+/// A layer of the graph where a branch passes through a different resolution than the
+/// top branch.
+pub struct BranchDivergence {
+    /// The number of clusters of the layer.
+    #[getset(get_copy = "pub")]
+    clusters: usize,
+    /// The resolution of the branch in this layer.
+    #[getset(get_copy = "pub")]
+    resolution: f64,
+    /// The resolution of the top branch in this layer.
+    #[getset(get_copy = "pub")]
+    top_resolution: f64,
+}
+
+#[derive(CopyGetters, Getters, Clone, Debug, PartialEq, Serialize)]
+/// A branch of the graph ranked by its total stability.
+pub struct RankedBranch {
+    /// The rank of the branch, starting with `1` for the top branch.
+    #[getset(get_copy = "pub")]
+    rank: usize,
+    /// The sum of the stabilities of all transitions of the branch.
+    #[getset(get_copy = "pub")]
+    total_stability: f64,
+    /// The mean stability of the transitions of the branch.
+    #[getset(get_copy = "pub")]
+    mean_stability: f64,
+    /// The difference between the total stability of the top branch and this branch.
+    #[getset(get_copy = "pub")]
+    stability_gap: f64,
+    /// The resolutions of the branch ordered by increasing number of clusters.
+    #[getset(get = "pub")]
+    resolutions: Vec<f64>,
+    /// The layers where the branch differs from the top branch ordered by increasing number
+    /// of clusters.
+    #[getset(get = "pub")]
+    divergences: Vec<BranchDivergence>,
+}
+
+/// Returns the `k` root-to-leaf branches with the highest total stability ordered by
+/// decreasing total stability. Each branch starts with its leaf node and traces back
+/// to its root node like [`ResolutionNode::branch`].
+///
+/// # Parameters
+///
+/// * `resolutions` - the resolution data to build the graph from
+/// * `metric` - the metric used to score the stability of parent-child-transitions
+/// * `k` - the number of branches to return
+pub fn k_best_branches<M: StabilityMetric + ?Sized>(
+    resolutions: &[ResolutionData],
+    metric: &M,
+    k: usize,
+) -> Vec<Vec<Rc<ResolutionNode>>> {
+    let mut leaf_nodes: Vec<Rc<ResolutionNode>> = to_k_best_graph(resolutions, metric, k)
+        .into_iter()
+        .flatten()
+        .collect();
+    keep_best(&mut leaf_nodes, k);
+    leaf_nodes.iter().map(ResolutionNode::branch).collect()
+}
+
+/// Ranks the branches in the specified order and compares them to the first branch.
+///
+/// # Parameters
+///
+/// * `branches` - the branches ordered by decreasing total stability as returned by
+///   [`k_best_branches`]
+pub fn rank_branches(branches: &[Vec<Rc<ResolutionNode>>]) -> Vec<RankedBranch> {
+    let Some(top_branch) = branches.first() else {
+        return Vec::new();
+    };
+    let top_stability = top_branch
+        .first()
+        .map(|leaf| leaf.total_stability())
+        .unwrap_or(0.0);
+    branches
+        .iter()
+        .enumerate()
+        .map(|(index, branch)| {
+            let leaf = branch
+                .first()
+                .expect("A branch contains at least its root node.");
+            let divergences = branch
+                .iter()
+                .rev()
+                .zip(top_branch.iter().rev())
+                .filter(|(node, top_node)| node.resolution() != top_node.resolution())
+                .map(|(node, top_node)| BranchDivergence {
+                    clusters: node.number_of_clusters(),
+                    resolution: node.resolution(),
+                    top_resolution: top_node.resolution(),
+                })
+                .collect();
+            RankedBranch {
+                rank: index + 1,
+                total_stability: leaf.total_stability(),
+                mean_stability: leaf.mean_total_stability(),
+                stability_gap: top_stability - leaf.total_stability(),
+                resolutions: branch.iter().rev().map(|node| node.resolution()).collect(),
+                divergences,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_abs_diff_eq;
+
+    use super::*;
+    use crate::{
+        data::CellSample,
+        graph::{to_graph, top_branch},
+    };
+
+    /// A metric with arbitrary but distinct scores depending on the resolutions.
+    struct ResolutionMetric;
+
+    impl StabilityMetric for ResolutionMetric {
+        fn score(&self, parent: &ResolutionData, child: &ResolutionData) -> Result<f64, String> {
+            Ok((parent.resolution() * 7.0 + child.resolution() * 3.0)
+                .sin()
+                .abs())
+        }
+    }
+
+    /// Returns resolutions with 1, 2 and 3 clusters at two, three and two resolutions each.
+    fn layered_resolutions() -> Vec<ResolutionData> {
+        [
+            (0.1, 1),
+            (0.2, 1),
+            (0.3, 2),
+            (0.4, 2),
+            (0.5, 2),
+            (0.6, 3),
+            (0.7, 3),
+        ]
+        .into_iter()
+        .map(|(resolution, clusters)| {
+            let cells: Vec<CellSample> = (0..6)
+                .map(|cell_id| CellSample::new(cell_id, cell_id % clusters))
+                .collect();
+            ResolutionData::new(resolution, &cells)
+        })
+        .collect()
+    }
+
+    /// Returns the total stabilities of all root-to-leaf branches in decreasing order.
+    ///
+    /// # Parameters
+    ///
+    /// * `resolutions` - the resolution data to build the graph from
+    fn all_branch_stabilities(resolutions: &[ResolutionData]) -> Vec<f64> {
+        let layers = [&resolutions[0..2], &resolutions[2..5], &resolutions[5..7]];
+        let mut stabilities = Vec::new();
+        for root in layers[0] {
+            for middle in layers[1] {
+                for leaf in layers[2] {
+                    stabilities.push(
+                        ResolutionMetric.score(root, middle).unwrap()
+                            + ResolutionMetric.score(middle, leaf).unwrap(),
+                    );
+                }
+            }
+        }
+        stabilities.sort_by(|a, b| b.total_cmp(a));
+        stabilities
+    }
+
+    #[test]
+    fn test_k_best_branches() {
+        let resolutions = layered_resolutions();
+        let expected_stabilities = all_branch_stabilities(&resolutions);
+        let branches = k_best_branches(&resolutions, &ResolutionMetric, 5);
+        assert_eq!(branches.len(), 5);
+        for (branch, expected) in branches.iter().zip(&expected_stabilities) {
+            assert_eq!(branch.len(), 3);
+            assert_abs_diff_eq!(branch[0].total_stability(), *expected, epsilon = 1e-12);
+        }
+        // The best branch is the top branch of the optimal graph.
+        let top_branch = top_branch(&to_graph(&resolutions, &ResolutionMetric));
+        assert_eq!(branches[0], top_branch);
+        // Requesting more branches than exist returns all of them.
+        let branches = k_best_branches(&resolutions, &ResolutionMetric, 20);
+        assert_eq!(branches.len(), 12);
+        assert!(k_best_branches(&[], &ResolutionMetric, 3).is_empty());
+    }
+
+    #[test]
+    fn test_rank_branches() {
+        let resolutions = layered_resolutions();
+        let ranked_branches = rank_branches(&k_best_branches(&resolutions, &ResolutionMetric, 3));
+        assert_eq!(ranked_branches.len(), 3);
+        let top = &ranked_branches[0];
+        assert_eq!(top.rank(), 1);
+        assert_abs_diff_eq!(top.stability_gap(), 0.0);
+        assert!(top.divergences().is_empty());
+        assert_abs_diff_eq!(top.mean_stability(), top.total_stability() / 2.0);
+        for ranked_branch in &ranked_branches[1..] {
+            assert_abs_diff_eq!(
+                ranked_branch.stability_gap(),
+                top.total_stability() - ranked_branch.total_stability()
+            );
+            assert!(ranked_branch.stability_gap() >= 0.0);
+            assert!(!ranked_branch.divergences().is_empty());
+            for divergence in ranked_branch.divergences() {
+                let layer = divergence.clusters() - 1;
+                assert_eq!(divergence.resolution(), ranked_branch.resolutions()[layer]);
+                assert_eq!(divergence.top_resolution(), top.resolutions()[layer]);
+                assert_ne!(divergence.resolution(), divergence.top_resolution());
+            }
+        }
+        assert!(rank_branches(&[]).is_empty());
+    }
+}
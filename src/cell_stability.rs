@@ -0,0 +1,115 @@
+//! This module provides the stability of individual cells along a branch of clusterings.
+
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, HashMap},
+};
+
+use getset::CopyGetters;
+use serde::Serialize;
+
+use crate::data::ResolutionData;
+
+#[derive(CopyGetters, Clone, Debug, PartialEq, Serialize)]
+/// The stability of a single cell over all transitions of a branch.
+pub struct CellStability {
+    /// The ID of the cell.
+    #[getset(get_copy = "pub")]
+    cell_id: usize,
+    /// The mean relative overlap of the cell's child cluster with its parent cluster.
+    #[getset(get_copy = "pub")]
+    mean_stability: f64,
+    /// The lowest relative overlap of the cell's child cluster with its parent cluster.
+    #[getset(get_copy = "pub")]
+    minimum_stability: f64,
+    /// The number of transitions the cell was observed in.
+    #[getset(get_copy = "pub")]
+    transitions: usize,
+}
+
+/// Returns the stability of every cell over all parent-child-transitions between the specified
+/// clusterings ordered by cell ID.
+/// At each transition the stability of a cell is defined as the relative overlap of the
+/// cell's child cluster with the parent cluster the cell belongs to.
+///
+/// # Parameters
+///
+/// * `branch` - the clusterings of a branch
+pub fn cell_stabilities<T: Borrow<ResolutionData>>(branch: &[T]) -> Vec<CellStability> {
+    let mut branch: Vec<&ResolutionData> = branch.iter().map(Borrow::borrow).collect();
+    branch.sort_by_key(|resolution| resolution.clusters());
+    // Maps cell IDs to the sum, the minimum and the number of stabilities.
+    let mut cell_map: BTreeMap<usize, (f64, f64, usize)> = BTreeMap::new();
+    for pair in branch.windows(2) {
+        let (parent, child) = (pair[0], pair[1]);
+        let parent_of_cell: HashMap<usize, usize> = parent
+            .clustered_cells()
+            .iter()
+            .enumerate()
+            .flat_map(|(index, cluster)| cluster.cells().iter().map(move |cell| (*cell, index)))
+            .collect();
+        for child_cluster in child.clustered_cells() {
+            let mut overlaps: HashMap<usize, usize> = HashMap::new();
+            for cell in child_cluster.cells() {
+                if let Some(parent_index) = parent_of_cell.get(cell) {
+                    *overlaps.entry(*parent_index).or_insert(0) += 1;
+                }
+            }
+            let cluster_size = child_cluster.absolute_cluster_size() as f64;
+            for cell in child_cluster.cells() {
+                if let Some(parent_index) = parent_of_cell.get(cell) {
+                    let stability = overlaps[parent_index] as f64 / cluster_size;
+                    let entry = cell_map.entry(*cell).or_insert((0.0, f64::INFINITY, 0));
+                    entry.0 += stability;
+                    entry.1 = entry.1.min(stability);
+                    entry.2 += 1;
+                }
+            }
+        }
+    }
+    cell_map
+        .into_iter()
+        .map(|(cell_id, (sum, minimum, transitions))| CellStability {
+            cell_id,
+            mean_stability: sum / transitions as f64,
+            minimum_stability: minimum,
+            transitions,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_ulps_eq;
+
+    use super::*;
+    use crate::data::CellSample;
+
+    #[test]
+    fn test_cell_stabilities() {
+        let parent_cells: Vec<CellSample> = (0..8)
+            .map(|cell_id| CellSample::new(cell_id, cell_id / 4))
+            .collect();
+        // Cell 3 jumps from the first to the second lineage.
+        let child_cells: Vec<CellSample> = (0..8)
+            .map(|cell_id| CellSample::new(cell_id, if cell_id < 3 { 0 } else { 1 + cell_id / 6 }))
+            .collect();
+        let parent = ResolutionData::new(0.1, &parent_cells);
+        let child = ResolutionData::new(0.2, &child_cells);
+        let stabilities = cell_stabilities(&[&child, &parent]);
+        assert_eq!(stabilities.len(), 8);
+        assert_eq!(stabilities[3].cell_id(), 3);
+        assert_eq!(stabilities[3].transitions(), 1);
+        assert_ulps_eq!(stabilities[0].mean_stability(), 1.0);
+        assert_ulps_eq!(stabilities[3].mean_stability(), 1.0 / 3.0);
+        assert_ulps_eq!(stabilities[4].mean_stability(), 2.0 / 3.0);
+        assert_ulps_eq!(stabilities[7].minimum_stability(), 1.0);
+    }
+
+    #[test]
+    fn test_cell_stabilities_single_resolution() {
+        let cells: Vec<CellSample> = (0..8).map(|cell_id| CellSample::new(cell_id, 0)).collect();
+        let resolution = ResolutionData::new(0.1, &cells);
+        assert!(cell_stabilities(&[resolution]).is_empty());
+    }
+}
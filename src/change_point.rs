@@ -0,0 +1,308 @@
+//! This module detects abrupt changes of the stability along a branch.
+//!
+//! The stabilities are segmented into piecewise constant levels by the pruned exact linear
+//! time (PELT) algorithm, which minimises the sum of squared deviations from the segment means
+//! plus a penalty per change point.
+
+use std::rc::Rc;
+
+use getset::{CopyGetters, Getters};
+use serde::Serialize;
+
+use crate::{graph::ResolutionNode, optimisation::median};
+
+/// The smallest penalty per change point, so that perfect steps are not split further.
+const MINIMAL_PENALTY: f64 = 1e-9;
+
+#[derive(CopyGetters, Clone, Debug, PartialEq, Serialize)]
+/// A change of the stability level between two consecutive nodes of a branch.
+pub struct ChangePoint {
+    /// The number of clusters of the last node before the change, which is the candidate
+    /// optimal number of clusters.
+    #[getset(get_copy = "pub")]
+    clusters: usize,
+    /// The resolution of the last node before the change.
+    #[getset(get_copy = "pub")]
+    resolution: f64,
+    /// The number of clusters of the first node after the change.
+    #[getset(get_copy = "pub")]
+    next_clusters: usize,
+    /// The resolution of the first node after the change.
+    #[getset(get_copy = "pub")]
+    next_resolution: f64,
+    /// The mean stability of the segment before the change.
+    #[getset(get_copy = "pub")]
+    stability_before: f64,
+    /// The mean stability of the segment after the change.
+    #[getset(get_copy = "pub")]
+    stability_after: f64,
+    /// The reduction of the sum of squared deviations achieved by splitting the adjacent
+    /// segments at the change.
+    #[getset(get_copy = "pub")]
+    cost_reduction: f64,
+}
+
+#[derive(CopyGetters, Getters, Clone, Debug, PartialEq, Serialize)]
+/// The change points detected along a branch.
+pub struct ChangePointDetection {
+    /// The penalty per change point.
+    #[getset(get_copy = "pub")]
+    penalty: f64,
+    /// The sum of squared deviations of the stabilities from their segment means.
+    #[getset(get_copy = "pub")]
+    cost: f64,
+    /// The detected change points ordered by the number of clusters.
+    #[getset(get = "pub")]
+    change_points: Vec<ChangePoint>,
+}
+
+/// Detects changes of the stability level along the branch.
+/// If no penalty is specified, a BIC-like penalty of `2 σ² ln(n)` is used, where the noise
+/// variance `σ²` is estimated robustly from the differences of consecutive stabilities.
+///
+/// # Parameters
+///
+/// * `branch` - the branch to detect changes along
+/// * `penalty` - the penalty per change point
+pub fn detect_change_points(
+    branch: &[Rc<ResolutionNode>],
+    penalty: Option<f64>,
+) -> ChangePointDetection {
+    let mut nodes: Vec<&Rc<ResolutionNode>> = branch
+        .iter()
+        .filter(|node| node.optimal_stability().is_some())
+        .collect();
+    nodes.sort_by_key(|node| node.number_of_clusters());
+    let stabilities: Vec<f64> = nodes
+        .iter()
+        .filter_map(|node| node.optimal_stability())
+        .collect();
+    let penalty = penalty
+        .unwrap_or_else(|| default_penalty(&stabilities))
+        .max(MINIMAL_PENALTY);
+    let segment_ends = pelt(&stabilities, penalty);
+    let costs = SegmentCosts::new(&stabilities);
+    let cost = segment_ends
+        .iter()
+        .scan(0, |start, end| {
+            let cost = costs.cost(*start, *end);
+            *start = *end;
+            Some(cost)
+        })
+        .sum();
+    let boundaries: Vec<usize> = std::iter::once(0).chain(segment_ends).collect();
+    let change_points = boundaries
+        .windows(3)
+        .map(|segments| {
+            let (start, change, end) = (segments[0], segments[1], segments[2]);
+            ChangePoint {
+                clusters: nodes[change - 1].number_of_clusters(),
+                resolution: nodes[change - 1].resolution(),
+                next_clusters: nodes[change].number_of_clusters(),
+                next_resolution: nodes[change].resolution(),
+                stability_before: costs.mean(start, change),
+                stability_after: costs.mean(change, end),
+                cost_reduction: costs.cost(start, end)
+                    - costs.cost(start, change)
+                    - costs.cost(change, end),
+            }
+        })
+        .collect();
+    ChangePointDetection {
+        penalty,
+        cost,
+        change_points,
+    }
+}
+
+/// Returns the default penalty per change point of `2 σ² ln(n)`.
+/// The noise standard deviation `σ` is estimated by the median absolute difference of
+/// consecutive values, which is insensitive to the few differences spanning a change.
+/// If most consecutive values are identical, the median vanishes and `σ` is estimated
+/// by the root mean square of the differences instead.
+///
+/// # Parameters
+///
+/// * `values` - the values to segment
+fn default_penalty(values: &[f64]) -> f64 {
+    let differences: Vec<f64> = values
+        .windows(2)
+        .map(|pair| (pair[1] - pair[0]).abs())
+        .collect();
+    // The difference of two independent normal errors has `√2` times their standard deviation
+    // and its median absolute value is `0.6745` times its standard deviation.
+    let mut standard_deviation = median(&differences).unwrap_or(0.0) / (0.6745 * 2f64.sqrt());
+    if standard_deviation == 0.0 && !differences.is_empty() {
+        let mean_squared_difference = differences
+            .iter()
+            .map(|difference| difference.powi(2))
+            .sum::<f64>()
+            / differences.len() as f64;
+        standard_deviation = (mean_squared_difference / 2.0).sqrt();
+    }
+    2.0 * standard_deviation.powi(2) * (values.len().max(1) as f64).ln()
+}
+
+/// Returns the ends of the optimal segments of the values, which minimise the sum of
+/// squared deviations from the segment means plus the penalty per change point.
+///
+/// # Parameters
+///
+/// * `values` - the values to segment
+/// * `penalty` - the penalty per change point
+fn pelt(values: &[f64], penalty: f64) -> Vec<usize> {
+    if values.is_empty() {
+        return Vec::new();
+    }
+    let costs = SegmentCosts::new(values);
+    // The optimal penalised cost of the first `t` values and the start of its last segment.
+    let mut optimal_costs = vec![-penalty; values.len() + 1];
+    let mut last_starts = vec![0; values.len() + 1];
+    let mut candidates = vec![0];
+    for end in 1..=values.len() {
+        let (start, optimal_cost) = candidates
+            .iter()
+            .map(|start| (*start, optimal_costs[*start] + costs.cost(*start, end) + penalty))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .expect("The first value is always a candidate start.");
+        optimal_costs[end] = optimal_cost;
+        last_starts[end] = start;
+        // Starts that cannot become optimal for any later end are pruned.
+        candidates.retain(|start| optimal_costs[*start] + costs.cost(*start, end) <= optimal_cost);
+        candidates.push(end);
+    }
+    let mut segment_ends = vec![values.len()];
+    let mut end = values.len();
+    while last_starts[end] > 0 {
+        end = last_starts[end];
+        segment_ends.push(end);
+    }
+    segment_ends.reverse();
+    segment_ends
+}
+
+/// The cumulative sums of values used to calculate the cost of segments in constant time.
+struct SegmentCosts {
+    /// The sums of the first `i` values.
+    sums: Vec<f64>,
+    /// The sums of the squares of the first `i` values.
+    squared_sums: Vec<f64>,
+}
+
+impl SegmentCosts {
+    /// Creates the cumulative sums of the values.
+    ///
+    /// # Parameters
+    ///
+    /// * `values` - the values to segment
+    fn new(values: &[f64]) -> Self {
+        let mut sums = vec![0.0];
+        let mut squared_sums = vec![0.0];
+        for value in values {
+            sums.push(sums[sums.len() - 1] + value);
+            squared_sums.push(squared_sums[squared_sums.len() - 1] + value.powi(2));
+        }
+        Self { sums, squared_sums }
+    }
+
+    /// Returns the mean of the values in the segment `start..end`.
+    ///
+    /// # Parameters
+    ///
+    /// * `start` - the index of the first value of the segment
+    /// * `end` - the index after the last value of the segment
+    fn mean(&self, start: usize, end: usize) -> f64 {
+        (self.sums[end] - self.sums[start]) / (end - start) as f64
+    }
+
+    /// Returns the sum of squared deviations of the values in the segment `start..end`
+    /// from their mean.
+    ///
+    /// # Parameters
+    ///
+    /// * `start` - the index of the first value of the segment
+    /// * `end` - the index after the last value of the segment
+    fn cost(&self, start: usize, end: usize) -> f64 {
+        let sum = self.sums[end] - self.sums[start];
+        let cost =
+            self.squared_sums[end] - self.squared_sums[start] - sum.powi(2) / (end - start) as f64;
+        // Rounding errors must not produce negative costs.
+        cost.max(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_abs_diff_eq;
+
+    use super::*;
+
+    /// Returns a branch starting at 2 clusters with the specified stabilities.
+    ///
+    /// # Parameters
+    ///
+    /// * `stabilities` - the stabilities of the branch nodes with a parent
+    fn branch_with_stabilities(stabilities: &[f64]) -> Vec<Rc<ResolutionNode>> {
+        let mut branch = vec![Rc::new(ResolutionNode::new(0.1, 1))];
+        for (index, stability) in stabilities.iter().enumerate() {
+            let parent = branch.last().unwrap();
+            let clusters = index + 2;
+            let node = ResolutionNode::new_with_parent(
+                clusters as f64 / 10.0,
+                clusters,
+                parent,
+                *stability,
+            );
+            branch.push(Rc::new(node));
+        }
+        branch
+    }
+
+    #[test]
+    fn test_pelt() {
+        assert_eq!(pelt(&[0.9, 0.9, 0.9, 0.5, 0.5, 0.5], 0.01), vec![3, 6]);
+        assert_eq!(pelt(&[0.9, 0.9, 0.6, 0.6, 0.3, 0.3], 0.01), vec![2, 4, 6]);
+        assert_eq!(pelt(&[0.7, 0.7, 0.7], 0.01), vec![3]);
+        // A penalty larger than the gain of any split yields a single segment.
+        assert_eq!(pelt(&[0.9, 0.9, 0.9, 0.5, 0.5, 0.5], 1.0), vec![6]);
+        assert!(pelt(&[], 0.01).is_empty());
+    }
+
+    #[test]
+    fn test_detect_change_points() {
+        let stabilities = [0.91, 0.9, 0.92, 0.9, 0.6, 0.61, 0.59, 0.6, 0.6];
+        let detection = detect_change_points(&branch_with_stabilities(&stabilities), None);
+        assert_eq!(detection.change_points().len(), 1);
+        let change_point = &detection.change_points()[0];
+        assert_eq!(change_point.clusters(), 5);
+        assert_eq!(change_point.next_clusters(), 6);
+        assert_abs_diff_eq!(change_point.resolution(), 0.5);
+        assert_abs_diff_eq!(change_point.stability_before(), 0.9075, epsilon = 1e-12);
+        assert_abs_diff_eq!(change_point.stability_after(), 0.6, epsilon = 1e-12);
+        assert!(change_point.cost_reduction() > detection.penalty());
+        assert!(detection.cost() < 1e-3);
+        let detection = detect_change_points(&branch_with_stabilities(&[]), None);
+        assert!(detection.change_points().is_empty());
+        assert_abs_diff_eq!(detection.cost(), 0.0);
+    }
+
+    #[test]
+    fn test_default_penalty() {
+        // Constant values have no noise.
+        assert_abs_diff_eq!(default_penalty(&[0.5, 0.5, 0.5]), 0.0);
+        // Alternating values differ by `0.2`, whose noise variance is `0.02 / 0.6745²`.
+        let penalty = default_penalty(&[0.5, 0.7, 0.5, 0.7, 0.5]);
+        let expected = 2.0 * 0.02 / 0.6745f64.powi(2) * 5f64.ln();
+        assert_abs_diff_eq!(penalty, expected, epsilon = 1e-12);
+        // A single small deviation among identical values has a vanishing median difference,
+        // so the noise variance is estimated from the mean squared difference of `0.01² / 4`.
+        let mut stabilities = [1.0; 9];
+        stabilities[4] = 0.99;
+        let penalty = default_penalty(&stabilities);
+        let expected = 2.0 * (0.0001 / 4.0) / 2.0 * 9f64.ln();
+        assert_abs_diff_eq!(penalty, expected, epsilon = 1e-12);
+        // The deviation is noise and not split off into a segment of its own.
+        let detection = detect_change_points(&branch_with_stabilities(&stabilities), None);
+        assert!(detection.change_points().is_empty());
+    }
+}
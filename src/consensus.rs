@@ -0,0 +1,327 @@
+//! This module provides co-clustering consensus over all sampled resolutions.
+//!
+//! Cells that share the same cluster in every resolution are indistinguishable with regard to
+//! co-clustering and are collapsed into atoms. The co-clustering matrix is thereby represented
+//! implicitly by the cluster signatures of the atoms, which keeps the memory requirements linear
+//! in the number of cells. Only atom pairs above the consensus cut-off are materialised.
+
+use std::{
+    borrow::Borrow,
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+};
+
+use getset::{CopyGetters, Getters};
+use serde::Serialize;
+
+use crate::data::ResolutionData;
+
+/// The tolerance used when converting the consensus cut-off to a number of resolutions.
+const CUTOFF_TOLERANCE: f64 = 1e-9;
+
+#[derive(CopyGetters, Getters, Debug)]
+/// The fraction of resolutions in which pairs of cells are clustered together.
+pub struct CoClusteringMatrix {
+    /// The number of resolutions the matrix is based on.
+    #[getset(get_copy = "pub")]
+    number_of_resolutions: usize,
+    /// The atom each cell belongs to.
+    #[getset(get = "pub")]
+    cell_atoms: BTreeMap<usize, usize>,
+    /// The cluster index at each resolution for every atom.
+    atom_signatures: Vec<Vec<usize>>,
+    /// The atoms present in each cluster of each resolution, where the resolutions are
+    /// ordered by decreasing number of clusters.
+    resolution_clusters: Vec<Vec<Vec<usize>>>,
+}
+
+#[derive(CopyGetters, Clone, Debug, PartialEq, Serialize)]
+/// A non-zero entry of the co-clustering matrix between two atoms.
+pub struct CoClusteringEntry {
+    /// The first atom.
+    #[getset(get_copy = "pub")]
+    atom_a: usize,
+    /// The second atom.
+    #[getset(get_copy = "pub")]
+    atom_b: usize,
+    /// The fraction of resolutions in which both atoms are clustered together.
+    #[getset(get_copy = "pub")]
+    co_clustering_fraction: f64,
+}
+
+#[derive(CopyGetters, Clone, Debug, PartialEq, Serialize)]
+/// The assignment of a cell to a consensus cluster.
+pub struct ConsensusAssignment {
+    /// The ID of the cell.
+    #[getset(get_copy = "pub")]
+    cell_id: usize,
+    /// The atom of cells with identical cluster membership in all resolutions.
+    #[getset(get_copy = "pub")]
+    atom: usize,
+    /// The consensus cluster.
+    #[getset(get_copy = "pub")]
+    consensus_cluster: usize,
+}
+
+impl CoClusteringMatrix {
+    /// Creates the co-clustering matrix of all cells present in the specified resolutions.
+    ///
+    /// # Parameters
+    ///
+    /// * `resolutions` - the clusterings sampled at different resolutions
+    pub fn new<T: Borrow<ResolutionData>>(resolutions: &[T]) -> Self {
+        let mut resolutions: Vec<&ResolutionData> =
+            resolutions.iter().map(Borrow::borrow).collect();
+        resolutions.sort_by_key(|resolution| Reverse(resolution.clusters()));
+        // Collects the cluster signature of every cell.
+        let mut cell_signatures: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for (resolution_index, resolution) in resolutions.iter().enumerate() {
+            for (cluster_index, cluster) in resolution.clustered_cells().iter().enumerate() {
+                for cell in cluster.cells() {
+                    cell_signatures
+                        .entry(*cell)
+                        .or_insert_with(|| vec![usize::MAX; resolutions.len()])[resolution_index] =
+                        cluster_index;
+                }
+            }
+        }
+        // Collapses cells with identical signatures into atoms.
+        let mut signature_atoms: HashMap<Vec<usize>, usize> = HashMap::new();
+        let mut atom_signatures: Vec<Vec<usize>> = Vec::new();
+        let mut cell_atoms: BTreeMap<usize, usize> = BTreeMap::new();
+        for (cell, signature) in cell_signatures {
+            let atom = *signature_atoms.entry(signature.clone()).or_insert_with(|| {
+                atom_signatures.push(signature);
+                atom_signatures.len() - 1
+            });
+            cell_atoms.insert(cell, atom);
+        }
+        let mut resolution_clusters: Vec<Vec<Vec<usize>>> = resolutions
+            .iter()
+            .map(|resolution| vec![Vec::new(); resolution.clusters()])
+            .collect();
+        for (atom, signature) in atom_signatures.iter().enumerate() {
+            for (resolution_index, cluster_index) in signature.iter().enumerate() {
+                if let Some(cluster) = resolution_clusters[resolution_index].get_mut(*cluster_index)
+                {
+                    cluster.push(atom);
+                }
+            }
+        }
+        Self {
+            number_of_resolutions: resolutions.len(),
+            cell_atoms,
+            atom_signatures,
+            resolution_clusters,
+        }
+    }
+
+    /// Returns the number of atoms.
+    pub fn number_of_atoms(&self) -> usize {
+        self.atom_signatures.len()
+    }
+
+    /// Returns the fraction of resolutions in which both atoms are clustered together.
+    ///
+    /// # Parameters
+    ///
+    /// * `atom_a` - the first atom
+    /// * `atom_b` - the second atom
+    pub fn atom_co_clustering_fraction(&self, atom_a: usize, atom_b: usize) -> f64 {
+        if self.number_of_resolutions == 0 {
+            return 0.0;
+        }
+        let shared = self.atom_signatures[atom_a]
+            .iter()
+            .zip(&self.atom_signatures[atom_b])
+            .filter(|(a, b)| a == b && **a != usize::MAX)
+            .count();
+        shared as f64 / self.number_of_resolutions as f64
+    }
+
+    /// Returns all entries between distinct atoms with a co-clustering fraction of at least
+    /// the specified cut-off.
+    ///
+    /// Two atoms that are clustered together in at least `m` out of `n` resolutions must be
+    /// clustered together in at least one of any `n - m + 1` resolutions. Candidate pairs are
+    /// thus only generated from the finest `n - m + 1` resolutions, where clusters are smallest.
+    ///
+    /// # Parameters
+    ///
+    /// * `cutoff` - the minimal co-clustering fraction in the interval `(0, 1]`
+    pub fn entries_above(&self, cutoff: f64) -> Vec<CoClusteringEntry> {
+        let number_of_atoms = self.number_of_atoms();
+        if self.number_of_resolutions == 0 || number_of_atoms == 0 {
+            return Vec::new();
+        }
+        let minimal_shared = ((cutoff * self.number_of_resolutions as f64) - CUTOFF_TOLERANCE)
+            .ceil()
+            .max(1.0) as usize;
+        let generating_resolutions = self.number_of_resolutions.saturating_sub(minimal_shared) + 1;
+        let mut entries = Vec::new();
+        let mut is_candidate = vec![false; number_of_atoms];
+        let mut candidates: Vec<usize> = Vec::new();
+        for atom_a in 0..number_of_atoms {
+            // Processes one row of the matrix at a time.
+            for resolution_index in 0..generating_resolutions {
+                let cluster_index = self.atom_signatures[atom_a][resolution_index];
+                if let Some(cluster) = self.resolution_clusters[resolution_index].get(cluster_index)
+                {
+                    for atom_b in cluster.iter().filter(|atom_b| **atom_b > atom_a) {
+                        if !is_candidate[*atom_b] {
+                            is_candidate[*atom_b] = true;
+                            candidates.push(*atom_b);
+                        }
+                    }
+                }
+            }
+            for atom_b in candidates.drain(..) {
+                is_candidate[atom_b] = false;
+                let co_clustering_fraction = self.atom_co_clustering_fraction(atom_a, atom_b);
+                if co_clustering_fraction + CUTOFF_TOLERANCE >= cutoff {
+                    entries.push(CoClusteringEntry {
+                        atom_a,
+                        atom_b,
+                        co_clustering_fraction,
+                    });
+                }
+            }
+        }
+        entries.sort_by_key(|entry| (entry.atom_a(), entry.atom_b()));
+        entries
+    }
+
+    /// Returns the consensus clustering ordered by cell ID, where consensus clusters are the
+    /// connected components of atoms with a co-clustering fraction of at least the specified cut-off.
+    /// Consensus clusters are numbered in order of their first cell.
+    ///
+    /// # Parameters
+    ///
+    /// * `cutoff` - the minimal co-clustering fraction in the interval `(0, 1]`
+    pub fn consensus_clustering(&self, cutoff: f64) -> Vec<ConsensusAssignment> {
+        let mut atom_parents: Vec<usize> = (0..self.number_of_atoms()).collect();
+        for entry in self.entries_above(cutoff) {
+            let root_a = find_root(&mut atom_parents, entry.atom_a());
+            let root_b = find_root(&mut atom_parents, entry.atom_b());
+            if root_a != root_b {
+                atom_parents[root_a.max(root_b)] = root_a.min(root_b);
+            }
+        }
+        let mut consensus_clusters: HashMap<usize, usize> = HashMap::new();
+        self.cell_atoms
+            .iter()
+            .map(|(cell_id, atom)| {
+                let root = find_root(&mut atom_parents, *atom);
+                let next_cluster = consensus_clusters.len();
+                ConsensusAssignment {
+                    cell_id: *cell_id,
+                    atom: *atom,
+                    consensus_cluster: *consensus_clusters.entry(root).or_insert(next_cluster),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Returns the root of the specified element in a disjoint-set forest and compresses its path.
+///
+/// # Parameters
+///
+/// * `parents` - the parent of each element
+/// * `element` - the element to find the root for
+fn find_root(parents: &mut [usize], element: usize) -> usize {
+    let mut root = element;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    let mut current = element;
+    while parents[current] != root {
+        let next = parents[current];
+        parents[current] = root;
+        current = next;
+    }
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_ulps_eq;
+
+    use super::*;
+    use crate::data::CellSample;
+
+    /// Returns 4 resolutions of 8 cells, where cell 3 alternates between both halves.
+    fn resolutions() -> Vec<ResolutionData> {
+        [(0.1, 2), (0.2, 2), (0.3, 4), (0.4, 4)]
+            .into_iter()
+            .enumerate()
+            .map(|(index, (resolution, clusters))| {
+                let cells: Vec<CellSample> = (0..8)
+                    .map(|cell_id| {
+                        let cell_position = if cell_id == 3 && index % 2 == 1 {
+                            4
+                        } else {
+                            cell_id
+                        };
+                        CellSample::new(cell_id, cell_position * clusters / 8)
+                    })
+                    .collect();
+                ResolutionData::new(resolution, &cells)
+            })
+            .collect()
+    }
+
+    /// Returns the co-clustering fraction of both cells.
+    fn co_clustering_fraction(matrix: &CoClusteringMatrix, cell_a: usize, cell_b: usize) -> f64 {
+        matrix
+            .atom_co_clustering_fraction(matrix.cell_atoms()[&cell_a], matrix.cell_atoms()[&cell_b])
+    }
+
+    #[test]
+    fn test_co_clustering_fraction() {
+        let matrix = CoClusteringMatrix::new(&resolutions());
+        assert_eq!(matrix.number_of_resolutions(), 4);
+        assert_eq!(matrix.cell_atoms().len(), 8);
+        assert_ulps_eq!(co_clustering_fraction(&matrix, 0, 1), 1.0);
+        assert_ulps_eq!(co_clustering_fraction(&matrix, 0, 2), 0.5);
+        assert_ulps_eq!(co_clustering_fraction(&matrix, 2, 3), 0.5);
+        assert_ulps_eq!(co_clustering_fraction(&matrix, 3, 4), 0.5);
+        assert_ulps_eq!(co_clustering_fraction(&matrix, 0, 7), 0.0);
+    }
+
+    #[test]
+    fn test_entries_above() {
+        let matrix = CoClusteringMatrix::new(&resolutions());
+        let entries = matrix.entries_above(0.5);
+        // All pairs of atoms with a fraction of at least 0.5 must be found.
+        for atom_a in 0..matrix.number_of_atoms() {
+            for atom_b in (atom_a + 1)..matrix.number_of_atoms() {
+                let expected = matrix.atom_co_clustering_fraction(atom_a, atom_b) >= 0.5;
+                let found = entries
+                    .iter()
+                    .any(|entry| entry.atom_a() == atom_a && entry.atom_b() == atom_b);
+                assert_eq!(expected, found);
+            }
+        }
+    }
+
+    #[test]
+    fn test_consensus_clustering() {
+        let matrix = CoClusteringMatrix::new(&resolutions());
+        let consensus = matrix.consensus_clustering(1.0);
+        assert_eq!(consensus.len(), 8);
+        assert_eq!(consensus[0].consensus_cluster(), consensus[1].consensus_cluster());
+        assert_ne!(consensus[1].consensus_cluster(), consensus[2].consensus_cluster());
+        let clusters: Vec<usize> = consensus
+            .iter()
+            .map(ConsensusAssignment::consensus_cluster)
+            .collect();
+        assert_eq!(clusters, vec![0, 0, 1, 2, 3, 3, 4, 4]);
+        let coarse_clusters: Vec<usize> = matrix
+            .consensus_clustering(0.5)
+            .iter()
+            .map(ConsensusAssignment::consensus_cluster)
+            .collect();
+        assert_eq!(coarse_clusters, vec![0; 8]);
+    }
+}
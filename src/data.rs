@@ -5,9 +5,14 @@ use std::{
     collections::{HashMap, HashSet},
 };
 
+use clap::ValueEnum;
 use getset::{CopyGetters, Getters};
+use serde::Serialize;
 
-use crate::optimisation::{cluster_overlaps_relative, cluster_stability};
+use crate::optimisation::{
+    cluster_overlaps_relative, cluster_stability, conditional_entropy, median, trimmed_mean,
+    weighted_mean,
+};
 
 #[derive(CopyGetters, Getters, Clone, Debug)]
 /// Cells grouped by cluster with an according resolution.
@@ -107,8 +112,21 @@ impl ResolutionData {
         self.clustered_cells.len()
     }
 
+    /// Returns the IDs of all clustered cells in ascending order.
+    /// Cells are ordered, so that a seeded random number generator produces reproducible
+    /// samples and permutations of them independent of the hashing order.
+    pub fn sorted_cells(&self) -> Vec<usize> {
+        let mut cells: Vec<usize> = self
+            .clustered_cells
+            .iter()
+            .flat_map(|cluster| cluster.cells().iter().copied())
+            .collect();
+        cells.sort();
+        cells
+    }
+
     /// Groups the cells by their respective clusters.
-    /// This does not perserve ordering of the clusters.
+    /// The clusters are ordered by cluster ID, so that results are reproducible.
     ///
     /// # Parameters
     ///
@@ -124,11 +142,14 @@ impl ResolutionData {
                 map.insert(cell.cluster(), vec![cell.id()]);
             }
         }
-        map.into_iter()
+        let mut clusters: Vec<Cluster> = map
+            .into_iter()
             .map(|(cluster_id, value)| {
-                Cluster::new(cluster_id, HashSet::from_iter(value.into_iter()), total_cell_number)
+                Cluster::new(cluster_id, HashSet::from_iter(value), total_cell_number)
             })
-            .collect()
+            .collect();
+        clusters.sort_by_key(|cluster| cluster.cluster_id());
+        clusters
     }
 }
 
@@ -157,11 +178,66 @@ impl CellSample {
 
 impl AsRef<CellSample> for CellSample {
     fn as_ref(&self) -> &Self {
-        &self
+        self
     }
 }
 
-#[derive(CopyGetters, Getters, Debug)]
+/// The methods to aggregate the stabilities of individual child clusters into
+/// a single stability score of a transition.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StabilityAggregation {
+    /// The unweighted mean of all child cluster stabilities.
+    Mean,
+    /// The mean of all child cluster stabilities weighted by the number of cells in each cluster.
+    CellWeightedMean,
+    /// The median of all child cluster stabilities.
+    Median,
+    /// The mean of all child cluster stabilities after removing the specified
+    /// proportion of the least and the most stable clusters each.
+    TrimmedMean(f64),
+}
+
+impl StabilityAggregation {
+    /// Returns the aggregated cluster stabilities.
+    /// Returns an error if there are no stabilities or if the trimmed proportion
+    /// of a [`StabilityAggregation::TrimmedMean`] is invalid.
+    ///
+    /// # Parameters
+    ///
+    /// * `stabilities` - the stabilities of the individual clusters
+    /// * `cluster_sizes` - the number of cells in each cluster
+    pub fn aggregate(
+        &self,
+        stabilities: &[f64],
+        cluster_sizes: &[usize],
+    ) -> Result<f64, &'static str> {
+        match self {
+            StabilityAggregation::Mean => {
+                let weights = vec![1.0; stabilities.len()];
+                weighted_mean(stabilities, &weights)
+            },
+            StabilityAggregation::CellWeightedMean => {
+                let weights: Vec<f64> = cluster_sizes.iter().map(|size| *size as f64).collect();
+                weighted_mean(stabilities, &weights)
+            },
+            StabilityAggregation::Median => median(stabilities),
+            StabilityAggregation::TrimmedMean(proportion) => trimmed_mean(stabilities, *proportion),
+        }
+    }
+}
+
+/// The directions in which the overlap of clusters between a parent and a child clustering is scored.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StabilityDirection {
+    /// Scores how well each child cluster fits into the parent clusters.
+    ChildToParent,
+    /// Scores how well each parent cluster is preserved by the child clusters.
+    ParentToChild,
+    /// The mean of both directional scores.
+    Symmetric,
+}
+
+#[derive(CopyGetters, Getters, Debug, Serialize)]
 /// Data associated with the stability of clusters observed at a specific resolution.
 pub struct ClusterStabilityData {
     /// The number of clusters in the parent clustering.
@@ -176,9 +252,36 @@ pub struct ClusterStabilityData {
     /// The resolution the child clustering was performed at.
     #[getset(get_copy = "pub")]
     child_resolution: f64,
+    /// The original identifiers of the child clusters.
+    #[getset(get = "pub")]
+    child_cluster_ids: Vec<usize>,
+    /// The number of cells in each child cluster.
+    #[getset(get = "pub")]
+    child_cluster_sizes: Vec<usize>,
     /// The stabilities of each child cluster.
     #[getset(get = "pub")]
     stabilities: Vec<f64>,
+    /// The original identifiers of the parent clusters.
+    #[getset(get = "pub")]
+    parent_cluster_ids: Vec<usize>,
+    /// The number of cells in each parent cluster.
+    #[getset(get = "pub")]
+    parent_cluster_sizes: Vec<usize>,
+    /// The reverse stabilities of each parent cluster, which are the sums of the
+    /// squared relative overlaps of the parent cluster with the child clusters.
+    #[getset(get = "pub")]
+    reverse_stabilities: Vec<f64>,
+    /// The aggregated stability score of the transition as scored by the stability metric,
+    /// which is the weight of the according edge of the cluster stability graph,
+    /// if the transition is part of a branch.
+    #[getset(get_copy = "pub")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score: Option<f64>,
+    /// The information theoretic comparison of the parent and child clustering if it has
+    /// been computed.
+    #[getset(get = "pub")]
+    #[serde(flatten)]
+    information: Option<TransitionInformation>,
 }
 
 impl ClusterStabilityData {
@@ -194,28 +297,30 @@ impl ClusterStabilityData {
         clustering_a: &ResolutionData,
         clustering_b: &ResolutionData,
     ) -> Result<Self, String> {
-        // Determines the parent child relation of the data based on the number of clusters.
-        let (parent_data, child_data) = if clustering_a.clusters() < clustering_b.clusters() {
-            (clustering_a, clustering_b)
-        } else if clustering_a.clusters() > clustering_b.clusters() {
-            (clustering_b, clustering_a)
-        } else {
-            return Err("The number of clusters is identical.".to_string());
-        };
-        // Calcultes the stabilites.
-        let stabilities = child_data
+        let (parent_data, child_data) = parent_and_child(clustering_a, clustering_b)?;
+        let parent_clusters: Vec<&HashSet<usize>> = parent_data
+            .clustered_cells()
+            .iter()
+            .map(Cluster::cells)
+            .collect();
+        let child_clusters: Vec<&HashSet<usize>> = child_data
             .clustered_cells()
+            .iter()
+            .map(Cluster::cells)
+            .collect();
+        // Calcultes the stabilites.
+        let stabilities = child_clusters
             .iter()
             .map(|cluster_child| {
-                cluster_stability(
-                    &parent_data
-                        .clustered_cells()
-                        .iter()
-                        .map(Cluster::cells)
-                        .collect::<Vec<&HashSet<usize>>>(),
-                    cluster_child.cells(),
-                )
-                .expect("The child cluster cannot be empty at this point.")
+                cluster_stability(&parent_clusters, *cluster_child)
+                    .expect("The child cluster cannot be empty at this point.")
+            })
+            .collect();
+        let reverse_stabilities = parent_clusters
+            .iter()
+            .map(|cluster_parent| {
+                cluster_stability(&child_clusters, *cluster_parent)
+                    .expect("The parent cluster cannot be empty at this point.")
             })
             .collect();
         Ok(Self {
@@ -223,14 +328,216 @@ impl ClusterStabilityData {
             clusters_child: child_data.clusters(),
             parent_resolution: parent_data.resolution(),
             child_resolution: child_data.resolution(),
+            child_cluster_ids: child_data
+                .clustered_cells()
+                .iter()
+                .map(Cluster::cluster_id)
+                .collect(),
+            child_cluster_sizes: child_data
+                .clustered_cells()
+                .iter()
+                .map(Cluster::absolute_cluster_size)
+                .collect(),
             stabilities,
+            parent_cluster_ids: parent_data
+                .clustered_cells()
+                .iter()
+                .map(Cluster::cluster_id)
+                .collect(),
+            parent_cluster_sizes: parent_data
+                .clustered_cells()
+                .iter()
+                .map(Cluster::absolute_cluster_size)
+                .collect(),
+            reverse_stabilities,
+            score: None,
+            information: None,
+        })
+    }
+
+    /// Creates clustering stability data like [`ClusterStabilityData::from_clustering`]
+    /// and additionally compares the clusterings by their entropies, which is more costly.
+    ///
+    /// # Parameters
+    ///
+    /// * `clustering_a` - the first clustering data with a specific resolution
+    /// * `clustering_b` - the second clustering data with a specific resolution
+    pub fn from_clustering_with_information(
+        clustering_a: &ResolutionData,
+        clustering_b: &ResolutionData,
+    ) -> Result<Self, String> {
+        Ok(Self {
+            information: Some(TransitionInformation::from_clustering(clustering_a, clustering_b)?),
+            ..Self::from_clustering(clustering_a, clustering_b)?
         })
     }
 
+    /// Sets the aggregated stability score of the transition.
+    ///
+    /// # Parameters
+    ///
+    /// * `score` - the score of the transition as scored by the stability metric
+    pub fn with_score(self, score: Option<f64>) -> Self {
+        Self { score, ..self }
+    }
+
     /// Returns the mean stability of all child clusters.
     pub fn mean_stability(&self) -> f64 {
         self.stabilities().iter().sum::<f64>() / (self.stabilities().len() as f64)
     }
+
+    /// Returns the stability of all child clusters aggregated by the specified method.
+    /// Returns an error if the trimmed proportion of a [`StabilityAggregation::TrimmedMean`] is invalid.
+    ///
+    /// # Parameters
+    ///
+    /// * `aggregation` - the method used to aggregate the child cluster stabilities
+    pub fn aggregated_stability(
+        &self,
+        aggregation: StabilityAggregation,
+    ) -> Result<f64, &'static str> {
+        aggregation.aggregate(self.stabilities(), self.child_cluster_sizes())
+    }
+
+    /// Returns the reverse stability of all parent clusters aggregated by the specified method.
+    /// Returns an error if the trimmed proportion of a [`StabilityAggregation::TrimmedMean`] is invalid.
+    ///
+    /// # Parameters
+    ///
+    /// * `aggregation` - the method used to aggregate the parent cluster stabilities
+    pub fn aggregated_reverse_stability(
+        &self,
+        aggregation: StabilityAggregation,
+    ) -> Result<f64, &'static str> {
+        aggregation.aggregate(self.reverse_stabilities(), self.parent_cluster_sizes())
+    }
+
+    /// Returns the aggregated stability of the transition in the specified direction.
+    /// Returns an error if the trimmed proportion of a [`StabilityAggregation::TrimmedMean`] is invalid.
+    ///
+    /// # Parameters
+    ///
+    /// * `direction` - the direction in which cluster overlaps are scored
+    /// * `aggregation` - the method used to aggregate the individual cluster stabilities
+    pub fn directional_stability(
+        &self,
+        direction: StabilityDirection,
+        aggregation: StabilityAggregation,
+    ) -> Result<f64, &'static str> {
+        match direction {
+            StabilityDirection::ChildToParent => self.aggregated_stability(aggregation),
+            StabilityDirection::ParentToChild => self.aggregated_reverse_stability(aggregation),
+            StabilityDirection::Symmetric => Ok((self.aggregated_stability(aggregation)?
+                + self.aggregated_reverse_stability(aggregation)?)
+                / 2.0),
+        }
+    }
+}
+
+#[derive(CopyGetters, Clone, Debug, PartialEq, Serialize)]
+/// The information theoretic comparison of a parent and a child clustering.
+pub struct TransitionInformation {
+    /// The entropy of the parent clustering.
+    #[getset(get_copy = "pub")]
+    parent_entropy: f64,
+    /// The conditional entropy `H(child|parent)`, which is the split component of the
+    /// variation of information.
+    #[getset(get_copy = "pub")]
+    conditional_entropy_child_given_parent: f64,
+    /// The conditional entropy `H(parent|child)`, which is the merge component of the
+    /// variation of information. It is zero for a transition that only splits parent clusters.
+    #[getset(get_copy = "pub")]
+    conditional_entropy_parent_given_child: f64,
+    /// The variation of information between the parent and child clustering.
+    #[getset(get_copy = "pub")]
+    variation_of_information: f64,
+}
+
+impl TransitionInformation {
+    /// Compares two clusterings performed at different resolutions by their entropies.
+    /// Returns an error if the number of clusters present in both datasets is identical
+    /// or if the clusterings do not contain any cells.
+    ///
+    /// # Parameters
+    ///
+    /// * `clustering_a` - the first clustering data with a specific resolution
+    /// * `clustering_b` - the second clustering data with a specific resolution
+    pub fn from_clustering(
+        clustering_a: &ResolutionData,
+        clustering_b: &ResolutionData,
+    ) -> Result<Self, String> {
+        let (parent_data, child_data) = parent_and_child(clustering_a, clustering_b)?;
+        let parent_clusters: Vec<&HashSet<usize>> = parent_data
+            .clustered_cells()
+            .iter()
+            .map(Cluster::cells)
+            .collect();
+        let child_clusters: Vec<&HashSet<usize>> = child_data
+            .clustered_cells()
+            .iter()
+            .map(Cluster::cells)
+            .collect();
+        let parent_entropy = parent_data
+            .clustered_cells()
+            .iter()
+            .map(Cluster::relative_cluster_size)
+            .filter(|probability| *probability > 0.0)
+            .map(|probability| -probability * probability.ln())
+            .sum();
+        let conditional_entropy_child_given_parent =
+            conditional_entropy(&child_clusters, &parent_clusters)?;
+        let conditional_entropy_parent_given_child =
+            conditional_entropy(&parent_clusters, &child_clusters)?;
+        Ok(Self {
+            parent_entropy,
+            conditional_entropy_child_given_parent,
+            conditional_entropy_parent_given_child,
+            variation_of_information: conditional_entropy_child_given_parent
+                + conditional_entropy_parent_given_child,
+        })
+    }
+
+    /// Returns the joint entropy `H(parent, child)` of the parent and child clustering.
+    pub fn joint_entropy(&self) -> f64 {
+        self.parent_entropy() + self.conditional_entropy_child_given_parent()
+    }
+}
+
+/// Returns the parent and child clustering ordered by their number of clusters.
+/// Returns an error if the number of clusters present in both datasets is identical.
+///
+/// # Parameters
+///
+/// * `clustering_a` - the first clustering data with a specific resolution
+/// * `clustering_b` - the second clustering data with a specific resolution
+fn parent_and_child<'a>(
+    clustering_a: &'a ResolutionData,
+    clustering_b: &'a ResolutionData,
+) -> Result<(&'a ResolutionData, &'a ResolutionData), String> {
+    if clustering_a.clusters() < clustering_b.clusters() {
+        Ok((clustering_a, clustering_b))
+    } else if clustering_a.clusters() > clustering_b.clusters() {
+        Ok((clustering_b, clustering_a))
+    } else {
+        Err("The number of clusters is identical.".to_string())
+    }
+}
+
+/// Returns a parent clustering of the specified number of cells in 2 clusters and a child
+/// clustering, which cleanly splits each parent cluster in two.
+///
+/// # Parameters
+///
+/// * `cells` - the number of cells, which must be a multiple of 4
+#[cfg(test)]
+pub fn split_clusterings(cells: usize) -> (ResolutionData, ResolutionData) {
+    let parent_cells: Vec<CellSample> = (0..cells)
+        .map(|cell_id| CellSample::new(cell_id, cell_id / (cells / 2)))
+        .collect();
+    let child_cells: Vec<CellSample> = (0..cells)
+        .map(|cell_id| CellSample::new(cell_id, cell_id / (cells / 4)))
+        .collect();
+    (ResolutionData::new(0.1, &parent_cells), ResolutionData::new(0.2, &child_cells))
 }
 
 #[cfg(test)]
@@ -261,7 +568,7 @@ mod tests {
         assert_eq!(grouped_cells.len(), clusters.len());
         for (i, cell_cluster) in grouped_cells.iter().enumerate() {
             let cells = cell_cluster.cells();
-            assert_eq!(cells.len(), cells_per_cluster as usize);
+            assert_eq!(cells.len(), cells_per_cluster);
             assert!(cells
                 .iter()
                 .all(|cell| { *cell < (clusters[i] + 1) * cells_per_cluster }))
@@ -301,7 +608,7 @@ mod tests {
         assert_eq!(grouped_cells.len(), clusters.len());
         for (i, cell_cluster) in grouped_cells.iter().enumerate() {
             let cells = cell_cluster.cells();
-            assert_eq!(cells.len(), cells_per_cluster as usize);
+            assert_eq!(cells.len(), cells_per_cluster);
             assert!(cells
                 .iter()
                 .all(|cell| { *cell < (clusters[i] + 1) * cells_per_cluster }))
@@ -341,4 +648,143 @@ mod tests {
         assert_eq!(cluster.best_parent(&parent_clusters), Ok(1));
         assert!(cluster.best_parent(&empty_parents).is_err());
     }
+
+    #[test]
+    fn test_cluster_stability_data_from_clustering() {
+        let parent_cells: Vec<CellSample> = (0..8)
+            .map(|cell_id| CellSample::new(cell_id, cell_id / 4))
+            .collect();
+        let child_cells: Vec<CellSample> = (0..8)
+            .map(|cell_id| CellSample::new(cell_id, if cell_id < 2 { 2 } else { cell_id / 4 }))
+            .collect();
+        let parent = ResolutionData::new(0.1, &parent_cells);
+        let child = ResolutionData::new(0.2, &child_cells);
+        let stability_data = ClusterStabilityData::from_clustering(&child, &parent).unwrap();
+        assert_eq!(stability_data.clusters_parent(), 2);
+        assert_eq!(stability_data.clusters_child(), 3);
+        assert_ulps_eq!(stability_data.parent_resolution(), 0.1);
+        assert_ulps_eq!(stability_data.child_resolution(), 0.2);
+        assert_ulps_eq!(
+            stability_data
+                .aggregated_stability(StabilityAggregation::Mean)
+                .unwrap(),
+            1.0
+        );
+        assert_eq!(stability_data.child_cluster_sizes().iter().sum::<usize>(), 8);
+        assert_ulps_eq!(
+            stability_data
+                .aggregated_stability(StabilityAggregation::CellWeightedMean)
+                .unwrap(),
+            1.0
+        );
+        // The information theoretic comparison is only computed on request.
+        assert!(stability_data.information().is_none());
+        let information = ClusterStabilityData::from_clustering_with_information(&child, &parent)
+            .unwrap()
+            .information()
+            .clone()
+            .unwrap();
+        assert_ulps_eq!(information.parent_entropy(), 2.0f64.ln());
+        assert_ulps_eq!(information.conditional_entropy_parent_given_child(), 0.0);
+        assert_ulps_eq!(
+            information.conditional_entropy_child_given_parent(),
+            information.variation_of_information()
+        );
+        assert!(information.variation_of_information() > 0.0);
+        assert!(ClusterStabilityData::from_clustering(&parent, &parent).is_err());
+    }
+
+    /// Returns a clustering with the specified cluster of each cell, where the cell IDs
+    /// are the indices of the labels.
+    ///
+    /// # Parameters
+    ///
+    /// * `resolution` - the resolution of the clustering
+    /// * `labels` - the cluster of each cell
+    fn clustering_from_labels(resolution: f64, labels: &[usize]) -> ResolutionData {
+        let cells: Vec<CellSample> = labels
+            .iter()
+            .enumerate()
+            .map(|(cell_id, cluster)| CellSample::new(cell_id, *cluster))
+            .collect();
+        ResolutionData::new(resolution, &cells)
+    }
+
+    /// Returns a parent clustering of 10 cells into clusters of 8 and 2 cells and a child
+    /// clustering into clusters of 6, 3 and 1 cells. The clusters of 6 and 1 cells are
+    /// contained in a single parent cluster and have a stability of `1`, while the cluster
+    /// of 3 cells straddles both parent clusters with overlaps of `2/3` and `1/3`
+    /// and has a stability of `5/9`.
+    fn straddling_clusterings() -> (ResolutionData, ResolutionData) {
+        (
+            clustering_from_labels(0.1, &[0, 0, 0, 0, 0, 0, 0, 0, 1, 1]),
+            clustering_from_labels(0.2, &[0, 0, 0, 0, 0, 0, 1, 1, 1, 2]),
+        )
+    }
+
+    #[test]
+    fn test_cluster_stability_data_aggregated_stability() {
+        let (parent, child) = straddling_clusterings();
+        let stability_data = ClusterStabilityData::from_clustering(&parent, &child).unwrap();
+        assert_ulps_eq!(
+            stability_data
+                .aggregated_stability(StabilityAggregation::Mean)
+                .unwrap(),
+            23.0 / 27.0
+        );
+        assert_ulps_eq!(
+            stability_data
+                .aggregated_stability(StabilityAggregation::CellWeightedMean)
+                .unwrap(),
+            13.0 / 15.0
+        );
+        assert_ulps_eq!(
+            stability_data
+                .aggregated_stability(StabilityAggregation::Median)
+                .unwrap(),
+            1.0
+        );
+        assert_ulps_eq!(
+            stability_data
+                .aggregated_stability(StabilityAggregation::TrimmedMean(0.4))
+                .unwrap(),
+            1.0
+        );
+        assert!(stability_data
+            .aggregated_stability(StabilityAggregation::TrimmedMean(0.6))
+            .is_err());
+    }
+
+    #[test]
+    fn test_cluster_stability_data_directional_stability() {
+        let parent_cells: Vec<CellSample> = (0..8)
+            .map(|cell_id| CellSample::new(cell_id, cell_id / 4))
+            .collect();
+        let child_cells: Vec<CellSample> = (0..8)
+            .map(|cell_id| CellSample::new(cell_id, if cell_id < 2 { 2 } else { cell_id / 4 }))
+            .collect();
+        let parent = ResolutionData::new(0.1, &parent_cells);
+        let child = ResolutionData::new(0.2, &child_cells);
+        let stability_data = ClusterStabilityData::from_clustering(&parent, &child).unwrap();
+        let aggregation = StabilityAggregation::Mean;
+        // The first parent cluster is split in half, the second one is preserved.
+        assert_ulps_eq!(
+            stability_data
+                .directional_stability(StabilityDirection::ChildToParent, aggregation)
+                .unwrap(),
+            1.0
+        );
+        assert_ulps_eq!(
+            stability_data
+                .directional_stability(StabilityDirection::ParentToChild, aggregation)
+                .unwrap(),
+            0.75
+        );
+        assert_ulps_eq!(
+            stability_data
+                .directional_stability(StabilityDirection::Symmetric, aggregation)
+                .unwrap(),
+            0.875
+        );
+    }
 }
@@ -0,0 +1,562 @@
+//! This module provides regression models defined by user formulas.
+//!
+//! A formula is an arithmetic expression in the number of clusters `x` and named parameters,
+//! such as `a / (x*b + c) + d`. It supports the operators `+`, `-`, `*`, `/` and `^` (or `**`),
+//! parentheses and the functions `exp`, `ln` (or `log`) and `sqrt`.
+
+use compute::optimize::{Powf, Var};
+
+use crate::optimisation::RegressionModel;
+
+/// The name of the independent variable in formulas.
+const VARIABLE_NAME: &str = "x";
+
+/// A regression model defined by a formula.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FormulaModel {
+    /// The formula as specified by the user.
+    formula: String,
+    /// The parsed formula.
+    expression: Expression,
+    /// The names of the parameters in order of their first occurrence in the formula.
+    parameter_names: Vec<String>,
+    /// The starting values of the parameters.
+    starting_values: Vec<f64>,
+}
+
+impl FormulaModel {
+    /// Parses the formula into a model, whose parameters all start from `1.0`.
+    /// Returns an error if the formula is invalid or has no parameters.
+    ///
+    /// # Parameters
+    ///
+    /// * `formula` - the formula to parse
+    pub fn parse(formula: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenise(formula)?,
+            position: 0,
+            parameter_names: Vec::new(),
+        };
+        let expression = parser.expression()?;
+        if let Some(token) = parser.tokens.get(parser.position) {
+            return Err(format!("Unexpected {} in the formula {}.", token, formula));
+        }
+        if parser.parameter_names.is_empty() {
+            return Err(format!("The formula {} has no parameters to fit.", formula));
+        }
+        Ok(Self {
+            formula: formula.trim().to_string(),
+            expression,
+            starting_values: vec![1.0; parser.parameter_names.len()],
+            parameter_names: parser.parameter_names,
+        })
+    }
+
+    /// Sets the starting values of the parameters.
+    /// Returns an error if a parameter has no starting value or a value is specified
+    /// for an unknown parameter.
+    ///
+    /// # Parameters
+    ///
+    /// * `starting_values` - the names of the parameters and their starting values
+    pub fn with_starting_values(
+        mut self,
+        starting_values: &[(String, f64)],
+    ) -> Result<Self, String> {
+        if let Some((name, _)) = starting_values
+            .iter()
+            .find(|(name, _)| !self.parameter_names.contains(name))
+        {
+            return Err(format!("The formula {} has no parameter {}.", self.formula, name));
+        }
+        for (index, parameter_name) in self.parameter_names.iter().enumerate() {
+            self.starting_values[index] = starting_values
+                .iter()
+                .rev()
+                .find(|(name, _)| name == parameter_name)
+                .map(|(_, value)| *value)
+                .ok_or_else(|| {
+                    format!("The parameter {} has no starting value.", parameter_name)
+                })?;
+        }
+        Ok(self)
+    }
+}
+
+impl RegressionModel for FormulaModel {
+    fn name(&self) -> String {
+        self.formula.clone()
+    }
+
+    fn formula(&self) -> Option<String> {
+        Some(self.formula.clone())
+    }
+
+    fn number_of_parameters(&self) -> usize {
+        self.parameter_names.len()
+    }
+
+    fn initial_estimates(&self, _x: &[f64], _y: &[f64], _decay_scale: f64) -> Vec<f64> {
+        self.starting_values.clone()
+    }
+
+    fn evaluate<'a>(&self, parameters: &[Var<'a>], x: f64) -> Var<'a> {
+        match self.expression.evaluate(parameters, x) {
+            Term::Variable(value) => value,
+            // Every formula has a parameter, which makes its value a variable of the tape.
+            Term::Constant(_) => unreachable!("A formula with parameters is never constant."),
+        }
+    }
+}
+
+/// The functions that can be used in formulas.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Function {
+    /// The exponential function.
+    Exp,
+    /// The natural logarithm.
+    Ln,
+    /// The square root.
+    Sqrt,
+}
+
+impl Function {
+    /// Returns the function with the specified name or `None` if there is no such function.
+    ///
+    /// # Parameters
+    ///
+    /// * `name` - the name of the function
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "exp" => Some(Function::Exp),
+            "ln" | "log" => Some(Function::Ln),
+            "sqrt" => Some(Function::Sqrt),
+            _ => None,
+        }
+    }
+}
+
+/// The binary operators that can be used in formulas.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operator {
+    /// Addition.
+    Add,
+    /// Subtraction.
+    Subtract,
+    /// Multiplication.
+    Multiply,
+    /// Division.
+    Divide,
+    /// Exponentiation.
+    Power,
+}
+
+/// A parsed formula.
+#[derive(Clone, Debug, PartialEq)]
+enum Expression {
+    /// A numeric constant.
+    Number(f64),
+    /// The number of clusters.
+    Variable,
+    /// The parameter with the specified index.
+    Parameter(usize),
+    /// The negation of an expression.
+    Negation(Box<Expression>),
+    /// A binary operation.
+    Operation(Operator, Box<Expression>, Box<Expression>),
+    /// A function applied to an expression.
+    Call(Function, Box<Expression>),
+}
+
+impl Expression {
+    /// Evaluates the expression for the specified parameters and number of clusters.
+    ///
+    /// # Parameters
+    ///
+    /// * `parameters` - the parameters of the formula
+    /// * `x` - the number of clusters
+    fn evaluate<'a>(&self, parameters: &[Var<'a>], x: f64) -> Term<'a> {
+        match self {
+            Expression::Number(value) => Term::Constant(*value),
+            Expression::Variable => Term::Constant(x),
+            Expression::Parameter(index) => Term::Variable(parameters[*index]),
+            Expression::Negation(operand) => match operand.evaluate(parameters, x) {
+                Term::Constant(value) => Term::Constant(-value),
+                Term::Variable(value) => Term::Variable(-value),
+            },
+            Expression::Operation(operator, left, right) => {
+                Term::apply(*operator, left.evaluate(parameters, x), right.evaluate(parameters, x))
+            },
+            Expression::Call(function, argument) => match argument.evaluate(parameters, x) {
+                Term::Constant(value) => Term::Constant(match function {
+                    Function::Exp => value.exp(),
+                    Function::Ln => value.ln(),
+                    Function::Sqrt => value.sqrt(),
+                }),
+                Term::Variable(value) => Term::Variable(match function {
+                    Function::Exp => value.exp(),
+                    Function::Ln => value.ln(),
+                    Function::Sqrt => value.sqrt(),
+                }),
+            },
+        }
+    }
+}
+
+/// The value of a subexpression, which is only recorded on the tape if it depends on
+/// a parameter.
+#[derive(Clone, Copy, Debug)]
+enum Term<'a> {
+    /// A value independent of the parameters.
+    Constant(f64),
+    /// A value depending on the parameters.
+    Variable(Var<'a>),
+}
+
+impl<'a> Term<'a> {
+    /// Applies the operator to both operands.
+    ///
+    /// # Parameters
+    ///
+    /// * `operator` - the operator to apply
+    /// * `left` - the left operand
+    /// * `right` - the right operand
+    fn apply(operator: Operator, left: Term<'a>, right: Term<'a>) -> Term<'a> {
+        match (left, right) {
+            (Term::Constant(left), Term::Constant(right)) => Term::Constant(match operator {
+                Operator::Add => left + right,
+                Operator::Subtract => left - right,
+                Operator::Multiply => left * right,
+                Operator::Divide => left / right,
+                Operator::Power => left.powf(right),
+            }),
+            (Term::Variable(left), Term::Constant(right)) => Term::Variable(match operator {
+                Operator::Add => left + right,
+                Operator::Subtract => left - right,
+                Operator::Multiply => left * right,
+                Operator::Divide => left / right,
+                Operator::Power if right.fract() == 0.0 && right.abs() <= i32::MAX as f64 => {
+                    left.powi(right as i32)
+                },
+                Operator::Power => Powf::powf(&left, right),
+            }),
+            (Term::Constant(left), Term::Variable(right)) => Term::Variable(match operator {
+                Operator::Add => left + right,
+                Operator::Subtract => left - right,
+                Operator::Multiply => left * right,
+                Operator::Divide => left / right,
+                // The power of a constant is expressed by the exponential function,
+                // whose derivative is tracked correctly.
+                Operator::Power => (right * left.ln()).exp(),
+            }),
+            (Term::Variable(left), Term::Variable(right)) => Term::Variable(match operator {
+                Operator::Add => left + right,
+                Operator::Subtract => left - right,
+                Operator::Multiply => left * right,
+                Operator::Divide => left / right,
+                Operator::Power => Powf::powf(&left, right),
+            }),
+        }
+    }
+}
+
+/// The tokens of a formula.
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// A numeric constant.
+    Number(f64),
+    /// The name of a variable, parameter or function.
+    Identifier(String),
+    /// A binary operator.
+    Operator(Operator),
+    /// An opening parenthesis.
+    Open,
+    /// A closing parenthesis.
+    Close,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "number {}", value),
+            Token::Identifier(name) => write!(f, "name {}", name),
+            Token::Operator(operator) => write!(f, "operator {:?}", operator),
+            Token::Open => write!(f, "opening parenthesis"),
+            Token::Close => write!(f, "closing parenthesis"),
+        }
+    }
+}
+
+/// Splits the formula into tokens.
+///
+/// # Parameters
+///
+/// * `formula` - the formula to split
+fn tokenise(formula: &str) -> Result<Vec<Token>, String> {
+    let characters: Vec<char> = formula.chars().collect();
+    let mut tokens = Vec::new();
+    let mut position = 0;
+    while let Some(character) = characters.get(position).copied() {
+        let start = position;
+        position += 1;
+        let token = match character {
+            ' ' | '\t' => continue,
+            '+' => Token::Operator(Operator::Add),
+            '-' => Token::Operator(Operator::Subtract),
+            '*' if characters.get(position) == Some(&'*') => {
+                position += 1;
+                Token::Operator(Operator::Power)
+            },
+            '*' => Token::Operator(Operator::Multiply),
+            '/' => Token::Operator(Operator::Divide),
+            '^' => Token::Operator(Operator::Power),
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '0'..='9' | '.' => {
+                while let Some(next) = characters.get(position) {
+                    let exponent_sign =
+                        matches!(next, '+' | '-') && matches!(characters[position - 1], 'e' | 'E');
+                    if next.is_ascii_digit() || matches!(next, '.' | 'e' | 'E') || exponent_sign {
+                        position += 1;
+                    } else {
+                        break;
+                    }
+                }
+                let number: String = characters[start..position].iter().collect();
+                Token::Number(
+                    number
+                        .parse()
+                        .map_err(|_| format!("Invalid number {} in the formula.", number))?,
+                )
+            },
+            character if character.is_ascii_alphabetic() || character == '_' => {
+                while characters
+                    .get(position)
+                    .is_some_and(|next| next.is_ascii_alphanumeric() || *next == '_')
+                {
+                    position += 1;
+                }
+                Token::Identifier(characters[start..position].iter().collect())
+            },
+            character => {
+                return Err(format!(
+                    "Unexpected character {} at position {} of the formula.",
+                    character,
+                    start + 1
+                ))
+            },
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser of formulas.
+struct Parser {
+    /// The tokens of the formula.
+    tokens: Vec<Token>,
+    /// The position of the next token.
+    position: usize,
+    /// The names of the parameters found so far.
+    parameter_names: Vec<String>,
+}
+
+impl Parser {
+    /// Returns the next token without consuming it.
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    /// Consumes and returns the next token or returns an error at the end of the formula.
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or("The formula ends unexpectedly.")?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    /// Parses a sum or difference of terms.
+    fn expression(&mut self) -> Result<Expression, String> {
+        let mut expression = self.term()?;
+        while let Some(Token::Operator(operator @ (Operator::Add | Operator::Subtract))) =
+            self.peek().cloned()
+        {
+            self.position += 1;
+            expression =
+                Expression::Operation(operator, Box::new(expression), Box::new(self.term()?));
+        }
+        Ok(expression)
+    }
+
+    /// Parses a product or quotient of factors.
+    fn term(&mut self) -> Result<Expression, String> {
+        let mut expression = self.factor()?;
+        while let Some(Token::Operator(operator @ (Operator::Multiply | Operator::Divide))) =
+            self.peek().cloned()
+        {
+            self.position += 1;
+            expression =
+                Expression::Operation(operator, Box::new(expression), Box::new(self.factor()?));
+        }
+        Ok(expression)
+    }
+
+    /// Parses a factor with an optional sign, where the sign binds weaker than powers.
+    fn factor(&mut self) -> Result<Expression, String> {
+        match self.peek() {
+            Some(Token::Operator(Operator::Subtract)) => {
+                self.position += 1;
+                Ok(Expression::Negation(Box::new(self.factor()?)))
+            },
+            Some(Token::Operator(Operator::Add)) => {
+                self.position += 1;
+                self.factor()
+            },
+            _ => self.power(),
+        }
+    }
+
+    /// Parses a right-associative power.
+    fn power(&mut self) -> Result<Expression, String> {
+        let base = self.primary()?;
+        if let Some(Token::Operator(Operator::Power)) = self.peek() {
+            self.position += 1;
+            let exponent = self.factor()?;
+            Ok(Expression::Operation(Operator::Power, Box::new(base), Box::new(exponent)))
+        } else {
+            Ok(base)
+        }
+    }
+
+    /// Parses a number, name, function call or parenthesised expression.
+    fn primary(&mut self) -> Result<Expression, String> {
+        match self.next()? {
+            Token::Number(value) => Ok(Expression::Number(value)),
+            Token::Identifier(name) if self.peek() == Some(&Token::Open) => {
+                let function = Function::from_name(&name)
+                    .ok_or_else(|| format!("Unknown function {} in the formula.", name))?;
+                self.position += 1;
+                let argument = self.parenthesised()?;
+                Ok(Expression::Call(function, Box::new(argument)))
+            },
+            Token::Identifier(name) if name == VARIABLE_NAME => Ok(Expression::Variable),
+            Token::Identifier(name) => {
+                let index = self
+                    .parameter_names
+                    .iter()
+                    .position(|parameter_name| *parameter_name == name)
+                    .unwrap_or_else(|| {
+                        self.parameter_names.push(name);
+                        self.parameter_names.len() - 1
+                    });
+                Ok(Expression::Parameter(index))
+            },
+            Token::Open => self.parenthesised(),
+            token => Err(format!("Unexpected {} in the formula.", token)),
+        }
+    }
+
+    /// Parses an expression followed by a closing parenthesis.
+    fn parenthesised(&mut self) -> Result<Expression, String> {
+        let expression = self.expression()?;
+        match self.next()? {
+            Token::Close => Ok(expression),
+            token => Err(format!("Expected a closing parenthesis instead of {}.", token)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_abs_diff_eq;
+    use compute::optimize::Tape;
+
+    use super::*;
+
+    /// Evaluates the model and returns its value and gradient.
+    ///
+    /// # Parameters
+    ///
+    /// * `model` - the model to evaluate
+    /// * `parameters` - the values of the parameters
+    /// * `x` - the number of clusters
+    fn evaluate(model: &FormulaModel, parameters: &[f64], x: f64) -> (f64, Vec<f64>) {
+        let tape = Tape::new();
+        let parameters: Vec<Var> = parameters
+            .iter()
+            .map(|value| tape.add_var(*value))
+            .collect();
+        let value = model.evaluate(&parameters, x);
+        (value.val(), value.grad().to_vec()[..parameters.len()].to_vec())
+    }
+
+    #[test]
+    fn test_parse() {
+        let model = FormulaModel::parse("a / (x*b + c) + d").unwrap();
+        assert_eq!(model.parameter_names, ["a", "b", "c", "d"]);
+        assert_eq!(model.name(), "a / (x*b + c) + d");
+        assert_eq!(model.number_of_parameters(), 4);
+        let (value, gradient) = evaluate(&model, &[2.0, 1.0, 1.0, 0.5], 3.0);
+        assert_abs_diff_eq!(value, 1.0, epsilon = 1e-12);
+        let expected_gradient = [0.25, -2.0 * 3.0 / 16.0, -2.0 / 16.0, 1.0];
+        for (derivative, expected) in gradient.iter().zip(expected_gradient) {
+            assert_abs_diff_eq!(*derivative, expected, epsilon = 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_operators_and_functions() {
+        let cases: [(&str, f64); 9] = [
+            ("a*exp(-b*x) + c", 2.0 * (-3.0f64).exp() + 1.0),
+            ("-a^2 + x", -4.0 + 3.0),
+            ("a**b**2", 2.0),
+            ("2^a + x^b", 4.0 + 3.0),
+            ("ln(a) + log(x) + sqrt(x*a)", 2f64.ln() + 3f64.ln() + 6f64.sqrt()),
+            ("a - b - c", 0.0),
+            ("a / b / 2", 1.0),
+            ("1.5e-1 * a + 2E1 * x", 0.3 + 60.0),
+            ("x^(-a) * +b", 1.0 / 9.0),
+        ];
+        for (formula, expected) in cases {
+            let model = FormulaModel::parse(formula).unwrap();
+            let parameters = vec![2.0, 1.0, 1.0][..model.number_of_parameters()].to_vec();
+            let (value, _) = evaluate(&model, &parameters, 3.0);
+            assert_abs_diff_eq!(value, expected, epsilon = 1e-12);
+        }
+        // The derivative of a constant raised to a parameter is `c^b ln(c)`.
+        let (_, gradient) = evaluate(&FormulaModel::parse("2^b").unwrap(), &[3.0], 1.0);
+        assert_abs_diff_eq!(gradient[0], 8.0 * 2f64.ln(), epsilon = 1e-12);
+    }
+
+    #[test]
+    fn test_invalid_formulas() {
+        for formula in [
+            "", "a +", "(a + x", "a + x)", "foo(a)", "a $ x", "2 * x", "a b",
+        ] {
+            assert!(FormulaModel::parse(formula).is_err(), "{}", formula);
+        }
+    }
+
+    #[test]
+    fn test_starting_values() {
+        let model = FormulaModel::parse("a*exp(-b*x) + c").unwrap();
+        assert_eq!(model.initial_estimates(&[], &[], 1.0), vec![1.0, 1.0, 1.0]);
+        let starting_values = [
+            ("c".to_string(), 0.5),
+            ("a".to_string(), 0.4),
+            ("b".to_string(), 0.2),
+        ];
+        let model = model.with_starting_values(&starting_values).unwrap();
+        assert_eq!(model.initial_estimates(&[], &[], 2.0), vec![0.4, 0.2, 0.5]);
+        assert!(model
+            .clone()
+            .with_starting_values(&starting_values[..2])
+            .is_err());
+        assert!(model
+            .with_starting_values(&[("z".to_string(), 1.0)])
+            .is_err());
+    }
+}
@@ -1,12 +1,13 @@
 use std::{borrow::Borrow, collections::HashMap, rc::Rc};
 
+use clap::ValueEnum;
 use getset::{CopyGetters, Getters};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     data::{Cluster, ResolutionData},
     graph::ResolutionNode,
-    optimisation::ClusterStabilityRegression,
+    optimisation::{knee, largest_second_difference, maximum_curvature, StabilityCurve},
 };
 
 #[derive(CopyGetters, Getters, Clone, Debug, Deserialize, Serialize)]
@@ -15,8 +16,10 @@ pub struct ClusterGenealogyNode {
     #[getset(get_copy = "pub")]
     /// The ID of the cluster.
     cluster_id: usize,
+    #[getset(get_copy = "pub")]
     /// The number of cells that belong to this cluster
     number_of_cells: usize,
+    #[getset(get = "pub")]
     /// The child cluster IDs.
     child_clusters: Vec<usize>,
 }
@@ -28,7 +31,11 @@ pub struct ClusterGenealogyEntry {
     #[getset(get_copy = "pub")]
     /// The number of clusters present at this resolution.
     number_of_clusters: usize,
+    #[getset(get_copy = "pub")]
+    /// The resolution the clusters have been sampled at.
     resolution: f64,
+    #[getset(get = "pub")]
+    /// The cluster nodes ordered by cluster ID.
     nodes: Vec<ClusterGenealogyNode>,
 }
 
@@ -42,7 +49,7 @@ impl ClusterGenealogyEntry {
     /// * `nodes` - the individual cluster nodes
     pub fn new(resolution_data: &ResolutionData, mut nodes: Vec<ClusterGenealogyNode>) -> Self {
         nodes.iter_mut().for_each(ClusterGenealogyNode::sort);
-        nodes.sort_by(|a, b| a.cluster_id().cmp(&b.cluster_id()));
+        nodes.sort_by_key(|a| a.cluster_id());
         Self {
             number_of_clusters: resolution_data.clusters(),
             resolution: resolution_data.resolution(),
@@ -123,7 +130,7 @@ impl ClusterGenealogyEntry {
             ));
             bottom_nodes = top_nodes.into_values().collect();
         }
-        entries.sort_by(|a, b| a.number_of_clusters().cmp(&b.number_of_clusters()));
+        entries.sort_by_key(|a| a.number_of_clusters());
         Ok(entries)
     }
 }
@@ -154,55 +161,241 @@ impl ClusterGenealogyNode {
 
     /// Sorts the child nodes by cluster ID.
     pub fn sort(&mut self) {
-        self.child_clusters.sort_by(|a, b| a.cmp(&b));
+        self.child_clusters.sort();
     }
 }
 
-/// Returns the according resolution data for a branch of cluster stability data or
-/// an error if any of the branch resolutions is not found in the specified [`ResolutionData`]
-/// pool.
+#[derive(CopyGetters, Clone, Debug, PartialEq, Serialize)]
+/// The crossing of the stability threshold by the regression of a branch.
+pub struct ThresholdCrossing {
+    /// The stability threshold.
+    #[getset(get_copy = "pub")]
+    threshold: f64,
+    /// The number of clusters at which the regression finally drops below the threshold or
+    /// `None` if it does not cross the threshold within the range of the branch.
+    #[getset(get_copy = "pub")]
+    clusters: Option<f64>,
+    /// The number of clusters of the finest node passing the threshold or `None` if no
+    /// node passes.
+    #[getset(get_copy = "pub")]
+    lower_clusters: Option<usize>,
+    /// The resolution of the finest node passing the threshold or `None` if no node passes.
+    #[getset(get_copy = "pub")]
+    lower_resolution: Option<f64>,
+    /// The number of clusters of the coarsest node failing the threshold or `None` if all
+    /// nodes pass.
+    #[getset(get_copy = "pub")]
+    upper_clusters: Option<usize>,
+    /// The resolution of the coarsest node failing the threshold or `None` if all nodes pass.
+    #[getset(get_copy = "pub")]
+    upper_resolution: Option<f64>,
+}
+
+/// Returns the crossing of the stability threshold by the regression within the range of
+/// the branch together with the nearest nodes on both sides.
+///
+/// # Parameters
+///
+/// * `branch` - the branch the regression has been fitted to
+/// * `regression` - the curve fitted to the branch stabilities
+/// * `threshold` - the stability threshold
+pub fn threshold_crossing(
+    branch: &[Rc<ResolutionNode>],
+    regression: &dyn StabilityCurve,
+    threshold: f64,
+) -> ThresholdCrossing {
+    let mut branch: Vec<&Rc<ResolutionNode>> = branch.iter().collect();
+    branch.sort_by_key(|node| node.number_of_clusters());
+    let x_min = branch.first().map(|node| node.number_of_clusters() as f64);
+    let x_max = branch.last().map(|node| node.number_of_clusters() as f64);
+    let clusters = x_min
+        .zip(x_max)
+        .and_then(|(x_min, x_max)| regression.threshold_crossing(threshold, x_min, x_max));
+    // Without a crossing the curve is either entirely above or below the threshold or only
+    // changes sides at a singularity, so the finest node predicted to pass is the cutoff.
+    let cutoff = clusters.unwrap_or_else(|| {
+        branch
+            .iter()
+            .rev()
+            .map(|node| node.number_of_clusters() as f64)
+            .find(|clusters| regression.predict(*clusters) >= threshold)
+            .unwrap_or(f64::NEG_INFINITY)
+    });
+    let lower = branch
+        .iter()
+        .rev()
+        .find(|node| node.number_of_clusters() as f64 <= cutoff);
+    let upper = branch
+        .iter()
+        .find(|node| node.number_of_clusters() as f64 > cutoff);
+    ThresholdCrossing {
+        threshold,
+        clusters,
+        lower_clusters: lower.map(|node| node.number_of_clusters()),
+        lower_resolution: lower.map(|node| node.resolution()),
+        upper_clusters: upper.map(|node| node.number_of_clusters()),
+        upper_resolution: upper.map(|node| node.resolution()),
+    }
+}
+
+/// The strategies to select the optimal clustering of a branch.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SelectionStrategy {
+    /// The finest clustering before the regression finally drops below the stability threshold.
+    Threshold,
+    /// The clustering nearest to the knee of the regression.
+    Knee,
+    /// The clustering nearest to the maximum curvature of the regression.
+    MaximumCurvature,
+    /// The clustering with the largest second difference of the observed stabilities.
+    SecondDifference,
+}
+
+#[derive(CopyGetters, Getters, Clone, Debug, PartialEq, Serialize)]
+/// The optimal clustering of a branch selected by a specific strategy.
+pub struct BranchSelection {
+    /// The strategy used to select the clustering.
+    #[getset(get_copy = "pub")]
+    strategy: SelectionStrategy,
+    /// The number of clusters the strategy located on the curve or `None` if the curve
+    /// has no such location.
+    #[getset(get_copy = "pub")]
+    clusters: Option<f64>,
+    /// The number of clusters of the selected clustering or `None` if no clustering has
+    /// been selected.
+    #[getset(get_copy = "pub")]
+    optimal_clusters: Option<usize>,
+    /// The resolution of the selected clustering or `None` if no clustering has been selected.
+    #[getset(get_copy = "pub")]
+    optimal_resolution: Option<f64>,
+    /// The crossing of the stability threshold if the threshold strategy is used.
+    #[getset(get = "pub")]
+    threshold_crossing: Option<ThresholdCrossing>,
+}
+
+/// Selects the optimal clustering of the branch with the specified strategy.
+/// The threshold strategy selects the finest node passing the threshold. The knee and
+/// maximum curvature strategies select the node nearest to the respective location on the
+/// regression, while the second difference strategy selects a node directly.
 ///
 /// # Parameters
 ///
-/// * `branch` - the branch to get the resolution data for
-/// * `resolutions` - the pool of all [`ResolutionData`]s
-pub fn branch_to_resolution_data<'a, 'b>(
-    branch: &'a [Rc<ResolutionNode>],
-    resolutions: &'b [ResolutionData],
-) -> Result<Vec<&'b ResolutionData>, &'static str> {
-    let mut branch_resolution_data = Vec::new();
-    for node in branch {
-        branch_resolution_data.push(
-            resolutions
+/// * `branch` - the branch the regression has been fitted to
+/// * `regression` - the curve fitted to the branch stabilities
+/// * `strategy` - the strategy used to select the clustering
+/// * `threshold` - the stability threshold used by the threshold strategy
+pub fn select_clustering(
+    branch: &[Rc<ResolutionNode>],
+    regression: &dyn StabilityCurve,
+    strategy: SelectionStrategy,
+    threshold: f64,
+) -> BranchSelection {
+    let mut observed: Vec<&Rc<ResolutionNode>> = branch
+        .iter()
+        .filter(|node| node.optimal_stability().is_some())
+        .collect();
+    observed.sort_by_key(|node| node.number_of_clusters());
+    let range = observed
+        .first()
+        .zip(observed.last())
+        .map(|(first, last)| (first.number_of_clusters() as f64, last.number_of_clusters() as f64));
+    let curve = |x| regression.predict(x);
+    let (clusters, selected, crossing) = match strategy {
+        SelectionStrategy::Threshold => {
+            let crossing = threshold_crossing(branch, regression, threshold);
+            let selected = branch
                 .iter()
-                .find(|resolution| resolution.resolution() == node.resolution())
-                .ok_or("The resolution pool does not contain all branch resolutions.")?,
-        );
+                .find(|node| Some(node.number_of_clusters()) == crossing.lower_clusters());
+            (crossing.clusters(), selected, Some(crossing))
+        },
+        SelectionStrategy::Knee | SelectionStrategy::MaximumCurvature => {
+            let clusters = range.and_then(|(x_min, x_max)| match strategy {
+                SelectionStrategy::Knee => knee(curve, x_min, x_max),
+                _ => maximum_curvature(curve, x_min, x_max),
+            });
+            // Ties are resolved in favour of the coarser clustering.
+            let selected = clusters.and_then(|clusters| {
+                observed.iter().copied().min_by(|a, b| {
+                    let distance = |node: &&Rc<ResolutionNode>| {
+                        (node.number_of_clusters() as f64 - clusters).abs()
+                    };
+                    distance(a).total_cmp(&distance(b))
+                })
+            });
+            (clusters, selected, None)
+        },
+        SelectionStrategy::SecondDifference => {
+            let stabilities: Vec<f64> = observed
+                .iter()
+                .filter_map(|node| node.optimal_stability())
+                .collect();
+            let selected = largest_second_difference(&stabilities).map(|index| observed[index]);
+            (selected.map(|node| node.number_of_clusters() as f64), selected, None)
+        },
+    };
+    BranchSelection {
+        strategy,
+        clusters,
+        optimal_clusters: selected.map(|node| node.number_of_clusters()),
+        optimal_resolution: selected.map(|node| node.resolution()),
+        threshold_crossing: crossing,
     }
-    Ok(branch_resolution_data)
 }
 
-/// Removes all nodes from the branch that do not pass the specified stability threshold.
+/// Removes all nodes from the branch that are finer than the selected clustering.
 ///
 /// # Parameters
 ///
 /// * `branch` - the branch to trim
-/// * `threshold` - the stability threshold
-pub fn trim_branch(branch: &[Rc<ResolutionNode>], threshold: f64) -> Vec<Rc<ResolutionNode>> {
-    let regression = ClusterStabilityRegression::new(branch);
-    let mut branch: Vec<Rc<ResolutionNode>> = branch.iter().map(Rc::clone).collect();
-    branch.sort_by(|a, b| a.number_of_clusters().cmp(&b.number_of_clusters()));
-    let mut trimmed_branch = Vec::new();
-    for node in branch.into_iter() {
-        if regression.predict(node.number_of_clusters() as f64) >= threshold {
-            // Keep nodes that are above the stability threshold.
-            trimmed_branch.push(node);
-        } else {
-            // When the threshold is crossed for the first time,
-            // discard all future clusterings.
-            break;
+/// * `selection` - the optimal clustering selected from the branch
+pub fn trim_branch(
+    branch: &[Rc<ResolutionNode>],
+    selection: &BranchSelection,
+) -> Vec<Rc<ResolutionNode>> {
+    let mut trimmed_branch: Vec<Rc<ResolutionNode>> = branch
+        .iter()
+        .filter(|node| {
+            selection
+                .optimal_clusters()
+                .map(|clusters| node.number_of_clusters() <= clusters)
+                .unwrap_or(false)
+        })
+        .map(Rc::clone)
+        .collect();
+    trimmed_branch.sort_by_key(|node| node.number_of_clusters());
+    trimmed_branch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A curve with a pole, at which it drops from above to below the threshold.
+    struct PoleCurve;
+
+    impl StabilityCurve for PoleCurve {
+        fn predict(&self, x: f64) -> f64 {
+            0.8 + 0.01 / (10.5 - x)
         }
     }
 
-    trimmed_branch
+    #[test]
+    fn test_threshold_crossing_at_pole() {
+        let mut branch = vec![Rc::new(ResolutionNode::new(0.0, 1))];
+        for clusters in 2..=30 {
+            let parent = branch.last().unwrap();
+            let node =
+                ResolutionNode::new_with_parent(clusters as f64 / 10.0, clusters, parent, 0.5);
+            branch.push(Rc::new(node));
+        }
+        let crossing = threshold_crossing(&branch, &PoleCurve, 0.8);
+        assert!(crossing.clusters().is_none());
+        // The sign change at the pole is rejected, but the nodes before it still pass.
+        assert_eq!(crossing.lower_clusters(), Some(10));
+        assert_eq!(crossing.upper_clusters(), Some(11));
+        let selection = select_clustering(&branch, &PoleCurve, SelectionStrategy::Threshold, 0.8);
+        assert_eq!(selection.optimal_clusters(), Some(10));
+        assert_eq!(trim_branch(&branch, &selection).len(), 10);
+    }
 }
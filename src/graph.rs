@@ -2,7 +2,10 @@ use std::{borrow::Borrow, collections::HashMap, rc::Rc};
 
 use getset::{CopyGetters, Getters};
 
-use crate::data::{ClusterStabilityData, ResolutionData};
+use crate::{
+    data::{ClusterStabilityData, ResolutionData},
+    metric::StabilityMetric,
+};
 
 /// Aggregates the [`ResolutionData`] vector by number of clusters present.
 ///
@@ -30,83 +33,214 @@ pub fn aggregate_by_number_of_clusters(
 /// # Parameters
 ///
 /// * `resolutions` - the resolution data to build the graph from
-pub fn to_graph(resolutions: &[ResolutionData]) -> Vec<Rc<ResolutionNode>> {
+/// * `metric` - the metric used to score the stability of parent-child-transitions
+pub fn to_graph<M: StabilityMetric + ?Sized>(
+    resolutions: &[ResolutionData],
+    metric: &M,
+) -> Vec<Rc<ResolutionNode>> {
+    // The optimal graph keeps only the best partial branch ending at every node.
+    to_k_best_graph(resolutions, metric, 1)
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// Returns the leaf nodes of the `k` partial branches with the highest total stability
+/// ending at every resolution of the last layer of a cluster stability graph, which is
+/// ordered in layers like [`to_graph`]. The leaf nodes of each resolution are ordered by
+/// decreasing total stability.
+///
+/// # Parameters
+///
+/// * `resolutions` - the resolution data to build the graph from
+/// * `metric` - the metric used to score the stability of parent-child-transitions
+/// * `k` - the number of partial branches kept for every node
+pub fn to_k_best_graph<M: StabilityMetric + ?Sized>(
+    resolutions: &[ResolutionData],
+    metric: &M,
+    k: usize,
+) -> Vec<Vec<Rc<ResolutionNode>>> {
     let map = aggregate_by_number_of_clusters(resolutions);
     let mut ordered_cluster_keys: Vec<usize> = map.keys().cloned().collect();
     ordered_cluster_keys.sort();
 
-    // Returns an empty vector if there are no clusters.
-    if ordered_cluster_keys.is_empty() {
-        return Vec::new();
-    }
-
-    let mut potential_parent_nodes: Vec<Rc<ResolutionNode>> = Vec::new();
-    let mut previous_cluster_key: Option<usize> = None;
-    for (i, cluster_key) in ordered_cluster_keys.into_iter().enumerate() {
-        let resolutions = map.get(&cluster_key).expect(
+    let mut previous_layer: Option<&Vec<&ResolutionData>> = None;
+    // The best partial branches ending at each node of the previous layer.
+    let mut potential_parent_nodes: Vec<Vec<Rc<ResolutionNode>>> = Vec::new();
+    for cluster_key in ordered_cluster_keys {
+        let layer = map.get(&cluster_key).expect(
             "The key was obtained directly from the map so there must be an associated value.",
         );
-        potential_parent_nodes = if i == 0 {
+        potential_parent_nodes = match previous_layer {
             // The first cluster elements do not have parent nodes.
-            resolutions
+            None => layer
                 .iter()
                 .map(|resolution| {
-                    Rc::new(ResolutionNode::new(resolution.resolution(), resolution.clusters()))
+                    vec![Rc::new(ResolutionNode::new(
+                        resolution.resolution(),
+                        resolution.clusters(),
+                    ))]
                 })
-                .collect()
-        } else {
+                .collect(),
             // Other cluster elements have parents and according stabilities.
-            let previous_resolutions = map.get(
-                            previous_cluster_key
-                                .as_ref()
-                                .expect("The previous key must have been set on a previous iteration."),
-                        )
-                        .expect(
-                            "The key was obtained directly from the map so there must be an associated value.",
-                        );
-            resolutions
+            Some(parent_layer) => layer
                 .iter()
                 .map(|resolution| {
-                    let mut optimal_node: Option<ResolutionNode> = None;
-                    for (i, potential_parent_node) in potential_parent_nodes.iter().enumerate() {
-                        let stability_data = ClusterStabilityData::from_clustering(
-                            resolution,
-                            &previous_resolutions[i],
-                        )
-                        .expect(
-                            "The number of clusters cannot be equal as sorting happend beforehand.",
-                        );
-                        let potential_child_node = ResolutionNode::new_with_parent(
-                            resolution.resolution(),
-                            resolution.clusters(),
-                            potential_parent_node,
-                            stability_data.mean_stability(),
-                        );
-                        // The optimal node has the highest overall stability and resolution.
-                        // Defaults to true if unset so that the optimal node gets set on the first iteration.
-                        if optimal_node.as_ref().map_or(true, |current_optimal_node| {
-                            potential_child_node.total_stability()
-                                > current_optimal_node.total_stability()
-                                || (potential_child_node.total_stability()
-                                    == current_optimal_node.total_stability())
-                                    && potential_child_node.resolution()
-                                        > current_optimal_node.resolution()
-                        }) {
-                            optimal_node = Some(potential_child_node)
-                        }
-                    }
-                    Rc::new(optimal_node.expect(
-                        "This must be set as there cannot be empty parent clustering data.",
-                    ))
+                    let mut potential_child_nodes: Vec<Rc<ResolutionNode>> = parent_layer
+                        .iter()
+                        .zip(&potential_parent_nodes)
+                        .flat_map(|(parent, parent_nodes)| {
+                            // Each transition is scored once for all partial branches
+                            // ending at the parent.
+                            let stability = metric.score(parent, resolution).expect(
+                                "The number of clusters cannot be equal as sorting happened \
+                                 beforehand.",
+                            );
+                            parent_nodes.iter().map(move |parent_node| {
+                                Rc::new(ResolutionNode::new_with_parent(
+                                    resolution.resolution(),
+                                    resolution.clusters(),
+                                    parent_node,
+                                    stability,
+                                ))
+                            })
+                        })
+                        .collect();
+                    keep_best(&mut potential_child_nodes, k);
+                    potential_child_nodes
                 })
-                .collect()
+                .collect(),
         };
-        previous_cluster_key = Some(cluster_key);
+        previous_layer = Some(layer);
     }
-    // Returns the optimal leaf nodes.
     potential_parent_nodes
 }
 
+/// Sorts the nodes by decreasing total stability and keeps the first `k` of them.
+/// Ties are resolved in favour of higher resolutions and otherwise of the first node.
+///
+/// # Parameters
+///
+/// * `nodes` - the nodes to sort
+/// * `k` - the number of nodes to keep
+pub fn keep_best(nodes: &mut Vec<Rc<ResolutionNode>>, k: usize) {
+    nodes.sort_by(|a, b| {
+        b.total_stability()
+            .total_cmp(&a.total_stability())
+            .then(b.resolution().total_cmp(&a.resolution()))
+    });
+    nodes.truncate(k);
+}
+
+/// Returns the branch leading to the leaf node with the highest total stability
+/// or an empty branch if there are no leaf nodes.
+///
+/// # Parameters
+///
+/// * `leaf_nodes` - the leaf nodes of a cluster stability graph
+pub fn top_branch(leaf_nodes: &[Rc<ResolutionNode>]) -> Vec<Rc<ResolutionNode>> {
+    leaf_nodes
+        .iter()
+        .max_by(|a, b| {
+            a.total_stability()
+                .partial_cmp(&b.total_stability())
+                .expect("There must only be valid stabilities.")
+        })
+        .map(ResolutionNode::branch)
+        .unwrap_or_default()
+}
+
+/// Returns the stability data of all parent-child-transitions along the specified branch
+/// ordered by increasing number of clusters or an error if any of the branch resolutions
+/// is not found in the specified [`ResolutionData`] pool.
+///
+/// # Parameters
+///
+/// * `branch` - the branch to get the transitions for
+/// * `resolutions` - the pool of all [`ResolutionData`]s
+pub fn branch_transitions(
+    branch: &[Rc<ResolutionNode>],
+    resolutions: &[ResolutionData],
+) -> Result<Vec<ClusterStabilityData>, String> {
+    map_branch_transitions(branch, resolutions, ClusterStabilityData::from_clustering)
+}
+
+/// Returns the stability data of all parent-child-transitions along the specified branch
+/// including the information theoretic comparison of the clusterings like
+/// [`branch_transitions`].
+///
+/// # Parameters
+///
+/// * `branch` - the branch to get the transitions for
+/// * `resolutions` - the pool of all [`ResolutionData`]s
+pub fn branch_transitions_with_information(
+    branch: &[Rc<ResolutionNode>],
+    resolutions: &[ResolutionData],
+) -> Result<Vec<ClusterStabilityData>, String> {
+    map_branch_transitions(
+        branch,
+        resolutions,
+        ClusterStabilityData::from_clustering_with_information,
+    )
+}
+
+/// Applies the transition function to all consecutive resolutions of the branch
+/// ordered by increasing number of clusters and adds the scores of the transitions.
+///
+/// # Parameters
+///
+/// * `branch` - the branch to get the transitions for
+/// * `resolutions` - the pool of all [`ResolutionData`]s
+/// * `transition` - the function creating the stability data of a transition
+fn map_branch_transitions<F>(
+    branch: &[Rc<ResolutionNode>],
+    resolutions: &[ResolutionData],
+    transition: F,
+) -> Result<Vec<ClusterStabilityData>, String>
+where
+    F: Fn(&ResolutionData, &ResolutionData) -> Result<ClusterStabilityData, String>,
+{
+    let mut branch_resolution_data: Vec<(&Rc<ResolutionNode>, &ResolutionData)> = branch
+        .iter()
+        .zip(branch_to_resolution_data(branch, resolutions)?)
+        .collect();
+    branch_resolution_data.sort_by_key(|(_, resolution)| resolution.clusters());
+    // The score of a transition is the optimal stability of its child node, as the parent
+    // precedes the child on the branch.
+    branch_resolution_data
+        .windows(2)
+        .map(|pair| {
+            let ((_, parent), (child_node, child)) = (pair[0], pair[1]);
+            transition(parent, child)
+                .map(|transition| transition.with_score(child_node.optimal_stability()))
+        })
+        .collect()
+}
+
+/// Returns the according resolution data for a branch of cluster stability data or
+/// an error if any of the branch resolutions is not found in the specified [`ResolutionData`]
+/// pool.
+///
+/// # Parameters
+///
+/// * `branch` - the branch to get the resolution data for
+/// * `resolutions` - the pool of all [`ResolutionData`]s
+pub fn branch_to_resolution_data<'b>(
+    branch: &[Rc<ResolutionNode>],
+    resolutions: &'b [ResolutionData],
+) -> Result<Vec<&'b ResolutionData>, &'static str> {
+    let mut branch_resolution_data = Vec::new();
+    for node in branch {
+        branch_resolution_data.push(
+            resolutions
+                .iter()
+                .find(|resolution| resolution.resolution() == node.resolution())
+                .ok_or("The resolution pool does not contain all branch resolutions.")?,
+        );
+    }
+    Ok(branch_resolution_data)
+}
+
 #[derive(CopyGetters, Getters, Debug, PartialEq, PartialOrd, Clone)]
 /// A node in a connected resolution graph, where edges are defined as cluster stability between nodes.
 pub struct ResolutionNode {
@@ -158,7 +292,7 @@ impl ResolutionNode {
     /// * `number_of_clusters` - the number of clusters present at the specified resolution
     /// * `optimal_parent` - stability-wise the optimal parent node for this child node
     /// * `optimal_stability` - the cluster stability of the optimal parent-child-transition.
-    /// including the stability for the transition of parent to child
+    ///   including the stability for the transition of parent to child
     pub fn new_with_parent<T: Borrow<Rc<Self>>>(
         resolution: f64,
         number_of_clusters: usize,
@@ -179,8 +313,8 @@ impl ResolutionNode {
     }
 
     /// Returns the mean total stability after reaching this node.
-    /// 
-    /// If applied to the root node this will return `1.0` as there is 
+    ///
+    /// If applied to the root node this will return `1.0` as there is
     /// no stability transition associated with it.
     pub fn mean_total_stability(&self) -> f64 {
         if self.depth() < 1 {
@@ -190,11 +324,11 @@ impl ResolutionNode {
         }
     }
 
-    /// Returns the branch leading to the specified node, starting with the specified node 
+    /// Returns the branch leading to the specified node, starting with the specified node
     /// and tracing back to a root node.
-    /// 
+    ///
     /// # Parameters
-    /// 
+    ///
     /// * `node` - the node to which to compute the branch for
     pub fn branch<T: Borrow<Rc<Self>>>(node: T) -> Vec<Rc<Self>> {
         let mut branch = Vec::new();
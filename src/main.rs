@@ -1,59 +1,360 @@
-use std::rc::Rc;
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
-use arguments::CommandLineArguments;
+use arguments::{Command, CommandLineArguments, PredictArguments};
+use bootstrap::bootstrap;
+use branch_ranking::{k_best_branches, rank_branches};
+use cell_stability::cell_stabilities;
+use change_point::detect_change_points;
 use clap::Parser;
-use genealogy::{branch_to_resolution_data, trim_branch, ClusterGenealogyEntry};
-use graph::{to_graph, ResolutionNode};
+use consensus::CoClusteringMatrix;
+use data::ResolutionData;
+use genealogy::{select_clustering, trim_branch, ClusterGenealogyEntry};
+use graph::{
+    branch_to_resolution_data, branch_transitions_with_information, to_graph, top_branch,
+    ResolutionNode,
+};
 use input::parse_input_csv;
-use plotting::plot_branch;
+use neighbour_graph::{branch_graph_quality, BranchGraphQuality, GraphQuality, NeighbourGraph};
+use null_model::{permutation_test, PermutationTestResult};
+use optimisation::{
+    predict_stabilities, select_model, FitMethod, ModelSpecification, StabilityCurve, StabilityFit,
+};
+use output::{write_csv, write_csv_to};
+use persistence::persistence_barcodes;
+use plotting::{plot_branch, PlotCurve};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Parses command line arguments.
     let cl_args = CommandLineArguments::parse();
-    let input_file = cl_args.csv_file();
+    if let Some(Command::Predict(predict_arguments)) = cl_args.command() {
+        return predict(predict_arguments);
+    }
+    let input_file = cl_args
+        .csv_file()
+        .as_deref()
+        .ok_or("No input CSV file has been specified.")?;
     let output_dir = cl_args.output_directory();
 
     // Builds the cluster stability graph.
     let resolution_data = parse_input_csv(input_file)?;
-    let result_graph = to_graph(&resolution_data);
-    let top_branch: Vec<Rc<ResolutionNode>> = result_graph
-        .iter()
-        .max_by(|a, b| {
-            a.total_stability()
-                .partial_cmp(&b.total_stability())
-                .expect("There must only be valid stabilities.")
-        })
-        .map(ResolutionNode::branch)
-        .unwrap_or(Vec::new());
+    let stability_metric = cl_args.metric()?;
+    let result_graph = to_graph(&resolution_data, stability_metric.as_ref());
+    let top_branch = top_branch(&result_graph);
 
-    // Plots the top branch
-    let output_graph_name = if let Some(file_name) = input_file.file_stem() {
-        format!("stability_graph_{}.svg", file_name.to_string_lossy())
-    } else {
-        "stability_graph_unknown_sample.svg".to_string()
+    // Fits the stability of the top branch.
+    let regression_settings = cl_args.regression_settings();
+    let observations = cl_args
+        .weighting()
+        .observations(&top_branch, &resolution_data)?;
+    let (regression, regression_model) = match (cl_args.fit_method(), cl_args.model_selection()) {
+        (FitMethod::Regression, Some(_)) if regression_settings.loss().is_robust() => {
+            return Err(
+                "Model selection requires the squared error loss, as information criteria \
+                 are not determined for robust losses."
+                    .into(),
+            );
+        },
+        (FitMethod::Regression, Some(criterion)) => {
+            if let Some((regression, model_selection)) =
+                select_model(&observations, criterion, &regression_settings)
+            {
+                let output_model_selection_path =
+                    output_file_path(&output_dir, input_file, "model_selection", "json");
+                serde_json::to_writer(
+                    std::fs::File::create(output_model_selection_path)?,
+                    &model_selection,
+                )?;
+                // If no model could be selected, the bootstrap replicates are fitted with the
+                // specified model.
+                let regression_model = match model_selection.selected_model() {
+                    Some(selected_model) => ModelSpecification::Named(selected_model),
+                    None => cl_args.model_specification()?,
+                };
+                (regression, regression_model)
+            } else {
+                // The branch is too short for any model, so the fit falls back to the
+                // observed stabilities.
+                let regression_model = cl_args.model_specification()?;
+                let regression = FitMethod::Regression.fit(
+                    &observations,
+                    &regression_model,
+                    &regression_settings,
+                );
+                (regression, regression_model)
+            }
+        },
+        (fit_method, _) => {
+            let regression_model = cl_args.model_specification()?;
+            let regression = fit_method.fit(&observations, &regression_model, &regression_settings);
+            (regression, regression_model)
+        },
     };
-    let output_graph_path = output_dir.join(output_graph_name);
-    plot_branch(&top_branch, output_graph_path)?;
 
-    let trimmed_top_branch = trim_branch(&top_branch, cl_args.stability_threashold());
+    // Computes the quality of all clusterings with regard to the neighbour graph.
+    let mut additional_curves = Vec::new();
+    if let Some(neighbour_graph_path) = cl_args.neighbour_graph() {
+        let neighbour_graph = NeighbourGraph::from_path(neighbour_graph_path)?;
+        let graph_qualities: Vec<GraphQuality> = resolution_data
+            .iter()
+            .map(|resolution| neighbour_graph.quality(resolution, cl_args.cpm_resolution()))
+            .collect();
+        let output_graph_quality_path =
+            output_file_path(&output_dir, input_file, "graph_quality", "json");
+        serde_json::to_writer(std::fs::File::create(output_graph_quality_path)?, &graph_qualities)?;
+        let top_branch_quality = branch_graph_quality(&top_branch, &graph_qualities);
+        let output_branch_quality_path =
+            output_file_path(&output_dir, input_file, "branch_graph_quality", "csv");
+        write_csv(&top_branch_quality, output_branch_quality_path)?;
+        let curve = |label: &str, value: fn(&BranchGraphQuality) -> f64| {
+            let mut points: Vec<(f64, f64)> = top_branch_quality
+                .iter()
+                .map(|quality| (quality.clusters() as f64, value(quality)))
+                .collect();
+            points.sort_by(|a, b| a.0.total_cmp(&b.0));
+            PlotCurve::new(label, points)
+        };
+        additional_curves.push(curve("modularity", BranchGraphQuality::modularity));
+        additional_curves.push(curve(
+            "intra-cluster edge fraction",
+            BranchGraphQuality::intra_cluster_edge_fraction,
+        ));
+        additional_curves.push(curve("mean conductance", BranchGraphQuality::mean_conductance));
+        // The CPM quality is a sum of edge weights, so it is scaled by its maximal absolute
+        // value on the branch to share the axis with the other curves.
+        let cpm_curve = curve("CPM quality (normalised)", BranchGraphQuality::cpm_quality);
+        let max_cpm_quality = cpm_curve
+            .points()
+            .iter()
+            .map(|(_, quality)| quality.abs())
+            .fold(0.0, f64::max);
+        if max_cpm_quality > 0.0 {
+            additional_curves.push(cpm_curve.scaled(1.0 / max_cpm_quality));
+        } else {
+            additional_curves.push(cpm_curve);
+        }
+    }
+
+    // Plots a fit saved by a previous run as reference.
+    if let Some(reference_fit_path) = cl_args.reference_fit() {
+        let reference_fit = load_fit(reference_fit_path)?;
+        let max_clusters = top_branch
+            .iter()
+            .map(|node| node.number_of_clusters())
+            .max()
+            .unwrap_or(1);
+        let points = (1..=max_clusters)
+            .map(|clusters| (clusters as f64, reference_fit.predict(clusters as f64)))
+            .collect();
+        additional_curves.push(PlotCurve::new("reference", points));
+    }
+
+    // Plots the top branch
+    let output_graph_path = output_file_path(&output_dir, input_file, "stability_graph", "svg");
+    plot_branch(&top_branch, &regression, &additional_curves, output_graph_path)?;
+
+    // Exports the transitions of the top branch.
+    let transitions = branch_transitions_with_information(&top_branch, &resolution_data)?;
+    let output_transitions_path = output_file_path(&output_dir, input_file, "transitions", "json");
+    serde_json::to_writer(std::fs::File::create(output_transitions_path)?, &transitions)?;
+
+    // Detects abrupt changes of the stability along the top branch.
+    if cl_args.change_points() {
+        let change_points = detect_change_points(&top_branch, cl_args.change_point_penalty());
+        let output_change_points_path =
+            output_file_path(&output_dir, input_file, "change_points", "json");
+        serde_json::to_writer(std::fs::File::create(output_change_points_path)?, &change_points)?;
+    }
+
+    // Ranks the branches with the highest total stability.
+    if let Some(k) = cl_args.top_branches() {
+        let ranked_branches =
+            rank_branches(&k_best_branches(&resolution_data, stability_metric.as_ref(), k));
+        let output_top_branches_path =
+            output_file_path(&output_dir, input_file, "top_branches", "json");
+        serde_json::to_writer(std::fs::File::create(output_top_branches_path)?, &ranked_branches)?;
+    }
+
+    // Tests the transitions of the top branch against a permutation null model.
+    if cl_args.permutations() > 0 {
+        let mut rng = ChaCha8Rng::seed_from_u64(cl_args.seed());
+        let mut top_branch_resolution_data =
+            branch_to_resolution_data(&top_branch, &resolution_data)?;
+        top_branch_resolution_data.sort_by_key(|resolution| resolution.clusters());
+        let permutation_tests = top_branch_resolution_data
+            .windows(2)
+            .map(|pair| permutation_test(pair[0], pair[1], cl_args.permutations(), &mut rng))
+            .collect::<Result<Vec<PermutationTestResult>, String>>()?;
+        let output_null_model_path =
+            output_file_path(&output_dir, input_file, "null_model", "json");
+        serde_json::to_writer(std::fs::File::create(output_null_model_path)?, &permutation_tests)?;
+    }
+
+    // Exports the stability of individual cells along the top branch.
+    let cell_stability =
+        cell_stabilities(&branch_to_resolution_data(&top_branch, &resolution_data)?);
+    let output_cell_stability_path =
+        output_file_path(&output_dir, input_file, "cell_stability", "csv");
+    write_csv(&cell_stability, output_cell_stability_path)?;
+
+    // Computes the co-clustering consensus over all resolutions.
+    if cl_args.consensus() {
+        let co_clustering = CoClusteringMatrix::new(&resolution_data);
+        let output_consensus_path = output_file_path(&output_dir, input_file, "consensus", "csv");
+        write_csv(
+            &co_clustering.consensus_clustering(cl_args.consensus_cutoff()),
+            output_consensus_path,
+        )?;
+        let output_co_clustering_path =
+            output_file_path(&output_dir, input_file, "co_clustering", "csv");
+        write_csv(
+            &co_clustering.entries_above(cl_args.consensus_cutoff()),
+            output_co_clustering_path,
+        )?;
+    }
+
+    // Exports the persistence of cluster lineages along the top branch.
+    let top_genealogy = ClusterGenealogyEntry::from_resolution_data(&branch_to_resolution_data(
+        &top_branch,
+        &resolution_data,
+    )?)?;
+    let output_persistence_path = output_file_path(&output_dir, input_file, "persistence", "json");
+    serde_json::to_writer(
+        std::fs::File::create(output_persistence_path)?,
+        &persistence_barcodes(&top_genealogy),
+    )?;
+
+    // Estimates the distribution of the optimal number of clusters.
+    if cl_args.bootstrap_replicates() > 0 {
+        let mut rng = ChaCha8Rng::seed_from_u64(cl_args.seed());
+        // The optimal clustering of a replicate is the finest clustering that remains
+        // after trimming its top branch.
+        let optimal_node = |branch: &[Rc<ResolutionNode>], resolutions: &[ResolutionData]| {
+            let regression = cl_args.fit_method().fit(
+                &cl_args.weighting().observations(branch, resolutions)?,
+                &regression_model,
+                &regression_settings,
+            );
+            let selection = select_clustering(
+                branch,
+                &regression,
+                cl_args.selection_strategy(),
+                cl_args.stability_threashold(),
+            );
+            Ok(trim_branch(branch, &selection).pop())
+        };
+        let bootstrap_summary = bootstrap(
+            &resolution_data,
+            stability_metric.as_ref(),
+            optimal_node,
+            cl_args.resampling_method(),
+            cl_args.bootstrap_replicates(),
+            &mut rng,
+        )?;
+        let output_bootstrap_path = output_file_path(&output_dir, input_file, "bootstrap", "json");
+        serde_json::to_writer(std::fs::File::create(output_bootstrap_path)?, &bootstrap_summary)?;
+    }
+
+    // Exports the regression of the top branch and warns if it is unreliable.
+    let output_regression_path = output_file_path(&output_dir, input_file, "regression", "json");
+    serde_json::to_writer(std::fs::File::create(output_regression_path)?, &regression)?;
+    if let Some(warning) = regression.fit_warning() {
+        eprintln!("Warning: {}", warning);
+    }
+
+    // Selects the optimal clustering of the top branch and exports the selection.
+    let selection = select_clustering(
+        &top_branch,
+        &regression,
+        cl_args.selection_strategy(),
+        cl_args.stability_threashold(),
+    );
+    if let Some(crossing) = selection.threshold_crossing() {
+        let output_crossing_path =
+            output_file_path(&output_dir, input_file, "threshold_crossing", "json");
+        serde_json::to_writer(std::fs::File::create(output_crossing_path)?, crossing)?;
+    }
+    let output_selection_path = output_file_path(&output_dir, input_file, "selection", "json");
+    serde_json::to_writer(std::fs::File::create(output_selection_path)?, &selection)?;
+
+    let trimmed_top_branch = trim_branch(&top_branch, &selection);
     let cluster_relation_tree = ClusterGenealogyEntry::from_resolution_data(
         &branch_to_resolution_data(&trimmed_top_branch, &resolution_data)?,
     )?;
-    let output_genealogy_name = if let Some(file_name) = input_file.file_stem() {
-        format!("genealogy_{}.json", file_name.to_string_lossy())
-    } else {
-        "genealogy_unknown_sample.json".to_string()
-    };
-    let output_genealogy_path = output_dir.join(output_genealogy_name);
+    let output_genealogy_path = output_file_path(&output_dir, input_file, "genealogy", "json");
 
     serde_json::to_writer(std::fs::File::create(output_genealogy_path)?, &cluster_relation_tree)?;
     Ok(())
 }
 
+/// Predicts the stability for the specified numbers of clusters from a saved fit.
+///
+/// # Parameters
+///
+/// * `arguments` - the arguments of the predict command
+fn predict(arguments: &PredictArguments) -> Result<(), Box<dyn std::error::Error>> {
+    let fit = load_fit(arguments.fit_file())?;
+    let predictions = predict_stabilities(&fit, arguments.clusters());
+    if let Some(output_file) = arguments.output_file() {
+        write_csv(&predictions, output_file)
+    } else {
+        write_csv_to(&predictions, std::io::stdout())
+    }
+}
+
+/// Loads a fit saved by a previous run.
+///
+/// # Parameters
+///
+/// * `fit_path` - the path of the saved fit
+fn load_fit(fit_path: &Path) -> Result<StabilityFit, Box<dyn std::error::Error>> {
+    let fit = serde_json::from_reader(BufReader::new(File::open(fit_path)?))?;
+    Ok(fit)
+}
+
+/// Returns the path of an output file named after the input sample.
+///
+/// # Parameters
+///
+/// * `output_dir` - the directory to write the output file to
+/// * `input_file` - the input file the sample name is derived from
+/// * `prefix` - the prefix describing the content of the output file
+/// * `extension` - the file extension of the output file
+fn output_file_path(
+    output_dir: &Path,
+    input_file: &Path,
+    prefix: &str,
+    extension: &str,
+) -> PathBuf {
+    let output_name = if let Some(file_name) = input_file.file_stem() {
+        format!("{}_{}.{}", prefix, file_name.to_string_lossy(), extension)
+    } else {
+        format!("{}_unknown_sample.{}", prefix, extension)
+    };
+    output_dir.join(output_name)
+}
+
 mod arguments;
+mod bootstrap;
+mod branch_ranking;
+mod cell_stability;
+mod change_point;
+mod consensus;
 mod data;
+mod formula;
 mod genealogy;
 mod graph;
 mod input;
+mod metric;
+mod neighbour_graph;
+mod null_model;
 mod optimisation;
+mod output;
+mod persistence;
 mod plotting;
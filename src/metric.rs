@@ -0,0 +1,146 @@
+//! This module provides metrics to score the stability of parent-child-transitions
+//! between clusterings sampled at different resolutions.
+
+use clap::ValueEnum;
+
+use crate::data::{
+    ClusterStabilityData, ResolutionData, StabilityAggregation, StabilityDirection,
+    TransitionInformation,
+};
+
+/// A metric scoring the stability of the transition from a parent to a child clustering.
+/// The scores are used as edge weights of the cluster stability graph and are thereby
+/// the basis of the stability regression and the trimming of branches.
+pub trait StabilityMetric {
+    /// Returns the stability score of the transition from the parent to the child clustering.
+    /// Scores are expected to lie in the interval `[0, 1]`, where higher scores indicate
+    /// more stable transitions.
+    /// Returns an error if the clusterings cannot be compared.
+    ///
+    /// # Parameters
+    ///
+    /// * `parent` - the clustering with fewer clusters
+    /// * `child` - the clustering with more clusters
+    fn score(&self, parent: &ResolutionData, child: &ResolutionData) -> Result<f64, String>;
+}
+
+/// The default metric, which is the aggregated stability of all child clusters,
+/// where the stability of each child cluster is the sum of its squared
+/// relative overlaps with the parent clusters.
+/// Alternatively the overlaps can be scored from the parent clusters' perspective
+/// or in both directions.
+#[derive(Clone, Copy, Debug)]
+pub struct SquaredOverlapMetric {
+    /// The direction in which cluster overlaps are scored.
+    direction: StabilityDirection,
+    /// The method used to aggregate the individual cluster stabilities.
+    aggregation: StabilityAggregation,
+}
+
+impl SquaredOverlapMetric {
+    /// Creates a new squared overlap metric.
+    ///
+    /// # Parameters
+    ///
+    /// * `direction` - the direction in which cluster overlaps are scored
+    /// * `aggregation` - the method used to aggregate the individual cluster stabilities
+    pub fn new(direction: StabilityDirection, aggregation: StabilityAggregation) -> Self {
+        Self {
+            direction,
+            aggregation,
+        }
+    }
+}
+
+impl Default for SquaredOverlapMetric {
+    fn default() -> Self {
+        Self::new(StabilityDirection::ChildToParent, StabilityAggregation::Mean)
+    }
+}
+
+impl StabilityMetric for SquaredOverlapMetric {
+    fn score(&self, parent: &ResolutionData, child: &ResolutionData) -> Result<f64, String> {
+        ClusterStabilityData::from_clustering(parent, child)?
+            .directional_stability(self.direction, self.aggregation)
+            .map_err(String::from)
+    }
+}
+
+/// A metric based on the variation of information between the parent and
+/// child clustering normalised by their joint entropy, so that a score of `1.0`
+/// corresponds to identical clusterings and a score of `0.0` to independent clusterings.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InformationMetric;
+
+impl StabilityMetric for InformationMetric {
+    fn score(&self, parent: &ResolutionData, child: &ResolutionData) -> Result<f64, String> {
+        let information = TransitionInformation::from_clustering(parent, child)?;
+        let joint_entropy = information.joint_entropy();
+        if joint_entropy > 0.0 {
+            Ok(1.0 - information.variation_of_information() / joint_entropy)
+        } else {
+            Ok(1.0)
+        }
+    }
+}
+
+/// The stability metrics that can be selected by name.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StabilityMetricType {
+    /// The mean squared relative overlap of child clusters with their parent clusters.
+    SquaredOverlap,
+    /// One minus the variation of information normalised by the joint entropy.
+    VariationOfInformation,
+}
+
+impl StabilityMetricType {
+    /// Returns the metric of this type.
+    ///
+    /// # Parameters
+    ///
+    /// * `direction` - the direction in which cluster overlaps are scored if applicable to the metric
+    /// * `aggregation` - the method used to aggregate the stabilities of individual clusters
+    ///   if applicable to the metric
+    pub fn metric(
+        &self,
+        direction: StabilityDirection,
+        aggregation: StabilityAggregation,
+    ) -> Box<dyn StabilityMetric> {
+        match self {
+            StabilityMetricType::SquaredOverlap => {
+                Box::new(SquaredOverlapMetric::new(direction, aggregation))
+            },
+            StabilityMetricType::VariationOfInformation => Box::new(InformationMetric),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_ulps_eq;
+
+    use super::*;
+    use crate::data::split_clusterings;
+
+    #[test]
+    fn test_squared_overlap_metric() {
+        let (parent, child) = split_clusterings(8);
+        let metric = SquaredOverlapMetric::default();
+        assert_ulps_eq!(1.0, metric.score(&parent, &child).unwrap());
+        assert!(metric.score(&parent, &parent).is_err());
+    }
+
+    #[test]
+    fn test_information_metric() {
+        let (parent, child) = split_clusterings(8);
+        // H(parent, child) = ln(4) and VI = ln(2).
+        assert_ulps_eq!(0.5, InformationMetric.score(&parent, &child).unwrap());
+        assert_ulps_eq!(
+            InformationMetric.score(&parent, &child).unwrap(),
+            StabilityMetricType::VariationOfInformation
+                .metric(StabilityDirection::Symmetric, StabilityAggregation::Mean)
+                .score(&parent, &child)
+                .unwrap()
+        );
+    }
+}
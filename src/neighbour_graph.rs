@@ -0,0 +1,396 @@
+//! This module handles the neighbour graph partitioned by the Leiden algorithm and
+//! the graph-based quality of clusterings.
+//!
+//! Nodes of the graph are identified by cell IDs, which correspond to the 1-based column
+//! index of the cell in the clustering CSV. Edges are undirected. If an edge is specified
+//! in both directions, the higher weight is used. Self-loops are ignored.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    rc::Rc,
+};
+
+use getset::{CopyGetters, Getters};
+use serde::Serialize;
+
+use crate::{data::ResolutionData, graph::ResolutionNode};
+
+#[derive(CopyGetters, Getters, Clone, Debug)]
+/// A weighted undirected neighbour graph of cells.
+pub struct NeighbourGraph {
+    /// The edges between pairs of cells and their weights.
+    #[getset(get = "pub")]
+    edges: Vec<(usize, usize, f64)>,
+    /// The weighted degree of each cell.
+    #[getset(get = "pub")]
+    degrees: HashMap<usize, f64>,
+    /// The sum of all edge weights.
+    #[getset(get_copy = "pub")]
+    total_weight: f64,
+}
+
+#[derive(CopyGetters, Getters, Clone, Debug, PartialEq, Serialize)]
+/// The quality of a clustering with regard to the neighbour graph.
+pub struct GraphQuality {
+    /// The resolution used for clustering.
+    #[getset(get_copy = "pub")]
+    resolution: f64,
+    /// The number of clusters.
+    #[getset(get_copy = "pub")]
+    clusters: usize,
+    /// The modularity of the clustering.
+    #[getset(get_copy = "pub")]
+    modularity: f64,
+    /// The resolution parameter `γ` of the constant Potts model used for the CPM quality.
+    #[getset(get_copy = "pub")]
+    cpm_resolution: f64,
+    /// The quality of the clustering according to the constant Potts model (CPM) with
+    /// the resolution parameter `cpm_resolution`. If it defaults to the clustering resolution,
+    /// the quality is only meaningful for clusterings computed with the CPM partition type.
+    #[getset(get_copy = "pub")]
+    cpm_quality: f64,
+    /// The fraction of the total edge weight within clusters.
+    #[getset(get_copy = "pub")]
+    intra_cluster_edge_fraction: f64,
+    /// The mean conductance of all clusters.
+    #[getset(get_copy = "pub")]
+    mean_conductance: f64,
+    /// The original identifiers of the clusters.
+    #[getset(get = "pub")]
+    cluster_ids: Vec<usize>,
+    /// The conductance of each cluster.
+    #[getset(get = "pub")]
+    conductances: Vec<f64>,
+}
+
+#[derive(CopyGetters, Clone, Debug, PartialEq, Serialize)]
+/// The stability and the graph quality of a single node of a branch.
+pub struct BranchGraphQuality {
+    /// The resolution used for clustering.
+    #[getset(get_copy = "pub")]
+    resolution: f64,
+    /// The number of clusters.
+    #[getset(get_copy = "pub")]
+    clusters: usize,
+    /// The stability of the transition from the optimal parent or `None` for the root node.
+    #[getset(get_copy = "pub")]
+    stability: Option<f64>,
+    /// The modularity of the clustering.
+    #[getset(get_copy = "pub")]
+    modularity: f64,
+    /// The resolution parameter `γ` of the constant Potts model used for the CPM quality.
+    #[getset(get_copy = "pub")]
+    cpm_resolution: f64,
+    /// The quality of the clustering according to the constant Potts model (CPM) with
+    /// the resolution parameter `cpm_resolution`.
+    #[getset(get_copy = "pub")]
+    cpm_quality: f64,
+    /// The fraction of the total edge weight within clusters.
+    #[getset(get_copy = "pub")]
+    intra_cluster_edge_fraction: f64,
+    /// The mean conductance of all clusters.
+    #[getset(get_copy = "pub")]
+    mean_conductance: f64,
+}
+
+impl NeighbourGraph {
+    /// Creates a new neighbour graph from a list of directed or undirected edges.
+    ///
+    /// # Parameters
+    ///
+    /// * `edges` - the edges between pairs of cells and their weights
+    pub fn new<I: IntoIterator<Item = (usize, usize, f64)>>(edges: I) -> Self {
+        let mut unique_edges: HashMap<(usize, usize), f64> = HashMap::new();
+        for (cell_a, cell_b, weight) in edges {
+            if cell_a != cell_b {
+                let key = (cell_a.min(cell_b), cell_a.max(cell_b));
+                let known_weight = unique_edges.entry(key).or_insert(weight);
+                *known_weight = known_weight.max(weight);
+            }
+        }
+        let mut edges: Vec<(usize, usize, f64)> = unique_edges
+            .into_iter()
+            .map(|((cell_a, cell_b), weight)| (cell_a, cell_b, weight))
+            .collect();
+        edges.sort_by_key(|(cell_a, cell_b, _)| (*cell_a, *cell_b));
+        let mut degrees: HashMap<usize, f64> = HashMap::new();
+        for (cell_a, cell_b, weight) in &edges {
+            *degrees.entry(*cell_a).or_insert(0.0) += weight;
+            *degrees.entry(*cell_b).or_insert(0.0) += weight;
+        }
+        let total_weight = edges.iter().map(|(_, _, weight)| weight).sum();
+        Self {
+            edges,
+            degrees,
+            total_weight,
+        }
+    }
+
+    /// Tries to parse the specified file as neighbour graph.
+    /// Files with the extension `mtx` are parsed as Matrix Market coordinate files,
+    /// all other files as edge lists.
+    ///
+    /// # Parameters
+    ///
+    /// * `path` - the path to the neighbour graph file
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let reader = BufReader::new(File::open(path.as_ref())?);
+        let is_matrix_market = path
+            .as_ref()
+            .extension()
+            .map(|extension| extension.eq_ignore_ascii_case("mtx"))
+            .unwrap_or(false);
+        if is_matrix_market {
+            Self::from_matrix_market(reader)
+        } else {
+            Self::from_edge_list(reader)
+        }
+    }
+
+    /// Tries to parse an edge list, where each line contains the IDs of two cells and
+    /// optionally an edge weight separated by whitespace or commas.
+    /// Empty lines and lines starting with `#` are skipped.
+    ///
+    /// # Parameters
+    ///
+    /// * `reader` - the reader to parse the edge list from
+    pub fn from_edge_list<R: BufRead>(reader: R) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut edges = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            edges.push(parse_edge(line)?);
+        }
+        Ok(Self::new(edges))
+    }
+
+    /// Tries to parse a Matrix Market coordinate file, where rows and columns
+    /// correspond to cell IDs.
+    ///
+    /// # Parameters
+    ///
+    /// * `reader` - the reader to parse the Matrix Market file from
+    pub fn from_matrix_market<R: BufRead>(reader: R) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut lines = reader.lines();
+        let header = lines.next().ok_or("The Matrix Market file is empty.")??;
+        let header = header.to_lowercase();
+        if !header.starts_with("%%matrixmarket matrix coordinate") {
+            return Err(format!("Unsupported Matrix Market header: {}", header).into());
+        }
+        let mut size_line_parsed = false;
+        let mut edges = Vec::new();
+        for line in lines {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('%') {
+                continue;
+            }
+            if !size_line_parsed {
+                // The first non-comment line contains the matrix dimensions.
+                size_line_parsed = true;
+                continue;
+            }
+            edges.push(parse_edge(line)?);
+        }
+        Ok(Self::new(edges))
+    }
+
+    /// Returns the quality of the specified clustering with regard to this graph.
+    ///
+    /// # Parameters
+    ///
+    /// * `clustering` - the clustering to evaluate
+    /// * `cpm_resolution` - the resolution parameter `γ` of the constant Potts model,
+    ///   which defaults to the resolution of the clustering if the Leiden algorithm optimised
+    ///   the constant Potts model
+    pub fn quality(
+        &self,
+        clustering: &ResolutionData,
+        cpm_resolution: Option<f64>,
+    ) -> GraphQuality {
+        let cpm_resolution = cpm_resolution.unwrap_or(clustering.resolution());
+        let cluster_of_cell: HashMap<usize, usize> = clustering
+            .clustered_cells()
+            .iter()
+            .enumerate()
+            .flat_map(|(index, cluster)| cluster.cells().iter().map(move |cell| (*cell, index)))
+            .collect();
+        let number_of_clusters = clustering.clusters();
+        let mut internal_weights = vec![0.0; number_of_clusters];
+        let mut cluster_degrees = vec![0.0; number_of_clusters];
+        for (cell, degree) in &self.degrees {
+            if let Some(cluster) = cluster_of_cell.get(cell) {
+                cluster_degrees[*cluster] += degree;
+            }
+        }
+        for (cell_a, cell_b, weight) in &self.edges {
+            if let (Some(cluster_a), Some(cluster_b)) =
+                (cluster_of_cell.get(cell_a), cluster_of_cell.get(cell_b))
+            {
+                if cluster_a == cluster_b {
+                    internal_weights[*cluster_a] += weight;
+                }
+            }
+        }
+        let total_weight = self.total_weight;
+        let total_degree = 2.0 * total_weight;
+        let mut modularity = 0.0;
+        let mut cpm_quality = 0.0;
+        let mut conductances = Vec::with_capacity(number_of_clusters);
+        for (index, cluster) in clustering.clustered_cells().iter().enumerate() {
+            let internal_weight = internal_weights[index];
+            let cluster_degree = cluster_degrees[index];
+            if total_weight > 0.0 {
+                modularity +=
+                    internal_weight / total_weight - (cluster_degree / total_degree).powi(2);
+            }
+            let size = cluster.absolute_cluster_size() as f64;
+            cpm_quality += internal_weight - cpm_resolution * size * (size - 1.0) / 2.0;
+            let volume = cluster_degree.min(total_degree - cluster_degree);
+            conductances.push(if volume > 0.0 {
+                (cluster_degree - 2.0 * internal_weight) / volume
+            } else {
+                0.0
+            });
+        }
+        let intra_cluster_edge_fraction = if total_weight > 0.0 {
+            internal_weights.iter().sum::<f64>() / total_weight
+        } else {
+            0.0
+        };
+        GraphQuality {
+            resolution: clustering.resolution(),
+            clusters: number_of_clusters,
+            modularity,
+            cpm_resolution,
+            cpm_quality,
+            intra_cluster_edge_fraction,
+            mean_conductance: conductances.iter().sum::<f64>() / number_of_clusters.max(1) as f64,
+            cluster_ids: clustering
+                .clustered_cells()
+                .iter()
+                .map(|cluster| cluster.cluster_id())
+                .collect(),
+            conductances,
+        }
+    }
+}
+
+/// Returns the graph quality of each node of the branch next to its stability.
+/// Nodes without a matching graph quality are skipped.
+///
+/// # Parameters
+///
+/// * `branch` - the branch of the stability graph
+/// * `qualities` - the graph qualities of all clusterings
+pub fn branch_graph_quality(
+    branch: &[Rc<ResolutionNode>],
+    qualities: &[GraphQuality],
+) -> Vec<BranchGraphQuality> {
+    branch
+        .iter()
+        .filter_map(|node| {
+            qualities
+                .iter()
+                .find(|quality| quality.resolution() == node.resolution())
+                .map(|quality| BranchGraphQuality {
+                    resolution: node.resolution(),
+                    clusters: node.number_of_clusters(),
+                    stability: node.optimal_stability(),
+                    modularity: quality.modularity(),
+                    cpm_resolution: quality.cpm_resolution(),
+                    cpm_quality: quality.cpm_quality(),
+                    intra_cluster_edge_fraction: quality.intra_cluster_edge_fraction(),
+                    mean_conductance: quality.mean_conductance(),
+                })
+        })
+        .collect()
+}
+
+/// Parses a single edge consisting of two cell IDs and an optional weight,
+/// which defaults to `1.0`.
+///
+/// # Parameters
+///
+/// * `line` - the line containing the edge
+fn parse_edge(line: &str) -> Result<(usize, usize, f64), String> {
+    let fields: Vec<&str> = line
+        .split(|character: char| character.is_whitespace() || character == ',')
+        .filter(|field| !field.is_empty())
+        .collect();
+    if fields.len() < 2 || fields.len() > 3 {
+        return Err(format!(
+            "The edge \"{}\" must consist of 2 cells and an optional weight.",
+            line
+        ));
+    }
+    let parse_cell = |field: &str| {
+        field.parse::<usize>().map_err(|error| {
+            format!("Parsing the cell {} of edge \"{}\" failed: {}", field, line, error)
+        })
+    };
+    let weight = match fields.get(2) {
+        Some(field) => field.parse::<f64>().map_err(|error| {
+            format!("Parsing the weight of edge \"{}\" failed: {}", line, error)
+        })?,
+        None => 1.0,
+    };
+    Ok((parse_cell(fields[0])?, parse_cell(fields[1])?, weight))
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_ulps_eq;
+
+    use super::*;
+    use crate::data::CellSample;
+
+    /// Returns two triangles of cells that are connected by a single edge.
+    const TRIANGLES: &str = "# Two triangles\n1 2\n2,3\n1 3 1.0\n3 4\n4 5\n5 6\n4 6\n2 1 0.5\n";
+
+    #[test]
+    fn test_from_edge_list() {
+        let graph = NeighbourGraph::from_edge_list(TRIANGLES.as_bytes()).unwrap();
+        assert_eq!(graph.edges().len(), 7);
+        assert_ulps_eq!(graph.total_weight(), 7.0);
+        assert_ulps_eq!(graph.degrees()[&3], 3.0);
+        assert!(NeighbourGraph::from_edge_list("1 2 3 4".as_bytes()).is_err());
+        assert!(NeighbourGraph::from_edge_list("1 a".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_from_matrix_market() {
+        let matrix_market = "%%MatrixMarket matrix coordinate pattern symmetric\n% comment\n6 6 7\n2 1\n3 2\n3 1\n4 3\n5 4\n6 5\n6 4\n";
+        let graph = NeighbourGraph::from_matrix_market(matrix_market.as_bytes()).unwrap();
+        assert_eq!(graph.edges().len(), 7);
+        assert_ulps_eq!(graph.total_weight(), 7.0);
+        assert!(NeighbourGraph::from_matrix_market("1 2\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_quality() {
+        let graph = NeighbourGraph::from_edge_list(TRIANGLES.as_bytes()).unwrap();
+        let cells: Vec<CellSample> = (1..=6)
+            .map(|cell_id| CellSample::new(cell_id, (cell_id - 1) / 3))
+            .collect();
+        let clustering = ResolutionData::new(0.1, &cells);
+        let quality = graph.quality(&clustering, None);
+        assert_eq!(quality.clusters(), 2);
+        assert_ulps_eq!(quality.modularity(), 6.0 / 7.0 - 0.5);
+        assert_ulps_eq!(quality.cpm_resolution(), 0.1);
+        assert_ulps_eq!(quality.cpm_quality(), 5.4);
+        assert_ulps_eq!(quality.intra_cluster_edge_fraction(), 6.0 / 7.0);
+        assert_ulps_eq!(quality.mean_conductance(), 1.0 / 7.0);
+        assert_eq!(quality.conductances().len(), 2);
+        // An explicit CPM resolution replaces the clustering resolution.
+        let quality = graph.quality(&clustering, Some(0.5));
+        assert_ulps_eq!(quality.cpm_resolution(), 0.5);
+        assert_ulps_eq!(quality.cpm_quality(), 3.0);
+    }
+}
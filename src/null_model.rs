@@ -0,0 +1,173 @@
+//! This module provides a permutation null model for the stability of transitions.
+//!
+//! The null distribution of the mean stability is sampled by randomly reassigning the cells
+//! of the child clustering to its clusters, which preserves the number and sizes of clusters.
+
+use getset::CopyGetters;
+use rand::{seq::SliceRandom, Rng};
+use serde::Serialize;
+
+use crate::data::{CellSample, ClusterStabilityData, ResolutionData};
+
+#[derive(CopyGetters, Clone, Debug, PartialEq, Serialize)]
+/// The result of a permutation test of the mean stability of a single transition.
+pub struct PermutationTestResult {
+    /// The number of clusters in the parent clustering.
+    #[getset(get_copy = "pub")]
+    clusters_parent: usize,
+    /// The number of clusters in the child clustering.
+    #[getset(get_copy = "pub")]
+    clusters_child: usize,
+    /// The resolution the parent clustering was performed at.
+    #[getset(get_copy = "pub")]
+    parent_resolution: f64,
+    /// The resolution the child clustering was performed at.
+    #[getset(get_copy = "pub")]
+    child_resolution: f64,
+    /// The observed mean stability.
+    #[getset(get_copy = "pub")]
+    observed_stability: f64,
+    /// The mean of the null distribution.
+    #[getset(get_copy = "pub")]
+    null_mean: f64,
+    /// The standard deviation of the null distribution.
+    #[getset(get_copy = "pub")]
+    null_standard_deviation: f64,
+    /// The z-score of the observed stability or `None` if the null distribution does not vary.
+    #[getset(get_copy = "pub")]
+    z_score: Option<f64>,
+    /// The empirical one-sided p-value of observing a stability at least as high by chance.
+    #[getset(get_copy = "pub")]
+    p_value: f64,
+    /// The number of permutations.
+    #[getset(get_copy = "pub")]
+    permutations: usize,
+}
+
+/// Tests the mean stability of the transition between both clusterings against
+/// a permutation null model.
+/// Returns an error if the clusterings cannot be compared or if no permutations are requested.
+///
+/// # Parameters
+///
+/// * `clustering_a` - the first clustering data with a specific resolution
+/// * `clustering_b` - the second clustering data with a specific resolution
+/// * `permutations` - the number of permutations used to sample the null distribution
+/// * `rng` - the random number generator used to permute the cells
+pub fn permutation_test<R: Rng + ?Sized>(
+    clustering_a: &ResolutionData,
+    clustering_b: &ResolutionData,
+    permutations: usize,
+    rng: &mut R,
+) -> Result<PermutationTestResult, String> {
+    if permutations == 0 {
+        return Err("At least one permutation is required.".to_string());
+    }
+    let observed = ClusterStabilityData::from_clustering(clustering_a, clustering_b)?;
+    let (parent, child) = if clustering_a.clusters() < clustering_b.clusters() {
+        (clustering_a, clustering_b)
+    } else {
+        (clustering_b, clustering_a)
+    };
+    let mut null_distribution = Vec::with_capacity(permutations);
+    for _ in 0..permutations {
+        let permuted_child = permute_cells(child, rng);
+        null_distribution
+            .push(ClusterStabilityData::from_clustering(parent, &permuted_child)?.mean_stability());
+    }
+    let null_mean = null_distribution.iter().sum::<f64>() / permutations as f64;
+    let null_standard_deviation = (null_distribution
+        .iter()
+        .map(|stability| (stability - null_mean).powi(2))
+        .sum::<f64>()
+        / permutations as f64)
+        .sqrt();
+    let observed_stability = observed.mean_stability();
+    let z_score = if null_standard_deviation > 0.0 {
+        Some((observed_stability - null_mean) / null_standard_deviation)
+    } else {
+        None
+    };
+    let exceeding = null_distribution
+        .iter()
+        .filter(|stability| **stability >= observed_stability)
+        .count();
+    Ok(PermutationTestResult {
+        clusters_parent: observed.clusters_parent(),
+        clusters_child: observed.clusters_child(),
+        parent_resolution: observed.parent_resolution(),
+        child_resolution: observed.child_resolution(),
+        observed_stability,
+        null_mean,
+        null_standard_deviation,
+        z_score,
+        p_value: (exceeding + 1) as f64 / (permutations + 1) as f64,
+        permutations,
+    })
+}
+
+/// Returns a copy of the clustering where cells have been randomly reassigned to clusters
+/// while preserving the cluster sizes.
+///
+/// # Parameters
+///
+/// * `clustering` - the clustering to permute
+/// * `rng` - the random number generator used to permute the cells
+fn permute_cells<R: Rng + ?Sized>(clustering: &ResolutionData, rng: &mut R) -> ResolutionData {
+    let mut cells = clustering.sorted_cells();
+    cells.shuffle(rng);
+    let mut remaining_cells = cells.into_iter();
+    let permuted_cells: Vec<CellSample> = clustering
+        .clustered_cells()
+        .iter()
+        .flat_map(|cluster| {
+            remaining_cells
+                .by_ref()
+                .take(cluster.absolute_cluster_size())
+                .map(|cell| CellSample::new(cell, cluster.cluster_id()))
+                .collect::<Vec<CellSample>>()
+        })
+        .collect();
+    ResolutionData::new(clustering.resolution(), &permuted_cells)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::data::split_clusterings;
+
+    #[test]
+    fn test_permute_cells() {
+        let (_, child) = split_clusterings(40);
+        let mut rng = ChaCha8Rng::seed_from_u64(42);
+        let permuted = permute_cells(&child, &mut rng);
+        let mut sizes: Vec<usize> = permuted
+            .clustered_cells()
+            .iter()
+            .map(|cluster| cluster.absolute_cluster_size())
+            .collect();
+        sizes.sort();
+        assert_eq!(sizes, vec![10, 10, 10, 10]);
+    }
+
+    #[test]
+    fn test_permutation_test() {
+        let (parent, child) = split_clusterings(40);
+        let result =
+            permutation_test(&parent, &child, 99, &mut ChaCha8Rng::seed_from_u64(42)).unwrap();
+        assert_eq!(result.permutations(), 99);
+        assert_eq!(result.clusters_parent(), 2);
+        assert_eq!(result.observed_stability(), 1.0);
+        assert!(result.null_mean() < result.observed_stability());
+        assert!(result.z_score().unwrap() > 0.0);
+        assert!(result.p_value() <= 0.05);
+        // The same seed must reproduce the same result.
+        let repeated =
+            permutation_test(&parent, &child, 99, &mut ChaCha8Rng::seed_from_u64(42)).unwrap();
+        assert_eq!(result, repeated);
+        assert!(permutation_test(&parent, &child, 0, &mut ChaCha8Rng::seed_from_u64(42)).is_err());
+    }
+}
@@ -2,15 +2,63 @@
 
 use std::{borrow::Borrow, collections::HashSet, rc::Rc};
 
+use clap::ValueEnum;
 use compute::{
-    linalg::Vector,
-    optimize::{Optimizer, Tape, Var, LM},
+    linalg::{Matrix, Vector},
+    optimize::{Gradient, Optimizer, Tape, Var, LM},
 };
+use getset::{CopyGetters, Getters};
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::graph::ResolutionNode;
+use crate::{
+    data::{ClusterStabilityData, ResolutionData},
+    formula::FormulaModel,
+    graph::{branch_transitions, ResolutionNode},
+};
 
-/// The initial parameter estimates to use for model fitting.
-const INITIAL_PARAMETER_ESTIMATES: [f64; 4] = [1.0, 1.0, -1.0, 0.5];
+/// The default maximum number of iterations of the regression optimiser.
+pub const DEFAULT_MAXIMUM_ITERATIONS: usize = 50;
+/// The default tolerance of the gradient of the squared residuals, at which the regression
+/// optimiser stops.
+pub const DEFAULT_GRADIENT_TOLERANCE: f64 = 1e-6;
+/// The default tolerance of the relative change of parameters, at which the regression
+/// optimiser stops.
+pub const DEFAULT_STEP_TOLERANCE: f64 = 1e-6;
+/// The default number of starting points of the regression optimiser.
+pub const DEFAULT_STARTS: usize = 5;
+/// The initial damping of the regression optimiser relative to the curvature of the residuals.
+const INITIAL_DAMPING: f64 = 1e-2;
+/// The number of additional iterations the optimiser is granted to check if it stopped
+/// due to its tolerance criteria.
+const SETTLING_ITERATIONS: usize = 8;
+/// The coefficient of determination below which a regression is considered poor.
+const POOR_FIT_R_SQUARED: f64 = 0.5;
+/// The maximum number of reweighting iterations of robust regressions.
+const MAXIMUM_ROBUST_ITERATIONS: usize = 20;
+/// The largest change of any weight, at which the reweighting of robust regressions stops.
+const ROBUST_WEIGHT_TOLERANCE: f64 = 1e-4;
+/// The tuning constant of the Huber loss giving 95% efficiency for normally distributed errors.
+const HUBER_TUNING_CONSTANT: f64 = 1.345;
+/// The tuning constant of the Tukey bisquare loss giving 95% efficiency for normally
+/// distributed errors.
+const TUKEY_TUNING_CONSTANT: f64 = 4.685;
+/// The number of intervals the range of clusters is divided into to analyse the shape of curves.
+const CURVE_SAMPLING_STEPS: usize = 1000;
+/// The smallest bend of a normalised curve that is not attributed to rounding errors.
+const BEND_TOLERANCE: f64 = 1e-9;
+/// The number of intervals the range of clusters is divided into to bracket threshold crossings.
+const CROSSING_SEARCH_STEPS: usize = 1000;
+/// The number of bisection steps used to refine a threshold crossing.
+const CROSSING_BISECTION_STEPS: usize = 60;
+/// The largest deviation of the predicted stability from the threshold at a crossing.
+const CROSSING_TOLERANCE: f64 = 1e-6;
+/// The smallest variance of the mean child cluster stability used to weight transitions.
+const MINIMAL_STABILITY_VARIANCE: f64 = 1e-6;
+/// The smallest amplitude used as starting estimate, so that the remaining
+/// parameters of a model contribute to the fit from the first iteration.
+const MINIMAL_AMPLITUDE_ESTIMATE: f64 = 0.01;
+/// The default fraction of observations used for each local fit of LOESS.
+pub const DEFAULT_LOESS_SPAN: f64 = 0.75;
 
 /// Returns the number of cells overlapping between the 2 clusters.
 ///
@@ -25,148 +73,2938 @@ pub fn cluster_overlap_absolute<A: Borrow<HashSet<usize>>, B: Borrow<HashSet<usi
     cluster_a.borrow().intersection(cluster_b.borrow()).count()
 }
 
-/// Returns the relative overlap of the child cluster with the parent cluster.
-/// Returns an error if the child cluster is empty.
+/// Returns the relative overlap of the child cluster with the parent cluster.
+/// Returns an error if the child cluster is empty.
+///
+/// # Parameters
+///
+/// * `cluster_parent` - the parent cluster to use as reference
+/// * `cluster_child` - the child cluster calculate the stability from
+pub fn cluster_overlap_relative<A: Borrow<HashSet<usize>>, B: Borrow<HashSet<usize>>>(
+    cluster_parent: A,
+    cluster_child: B,
+) -> Result<f64, String> {
+    if cluster_child.borrow().is_empty() {
+        Err("The child cluster is empty.".to_string())
+    } else {
+        let overlap = cluster_overlap_absolute(cluster_parent.borrow(), cluster_child.borrow());
+        Ok((overlap as f64) / (cluster_child.borrow().len() as f64))
+    }
+}
+
+/// Returns the relative overlaps of the child cluster with any of to the parent clusters.
+/// Returns an error if the child cluster is empty.
+///
+/// # Parameters
+///
+/// * `cluster_parent` - the parent cluster to use as reference
+/// * `cluster_child` - the child cluster calculate the stability from
+pub fn cluster_overlaps_relative<A: Borrow<HashSet<usize>>, B: Borrow<HashSet<usize>>>(
+    clusters_parent: &[A],
+    cluster_child: B,
+) -> Result<Vec<f64>, &'static str> {
+    if cluster_child.borrow().is_empty() {
+        Err("The child cluster is empty.")
+    } else {
+        let overlaps = clusters_parent
+            .iter()
+            .map(|cluster_parent| {
+                cluster_overlap_relative(cluster_parent.borrow(), cluster_child.borrow())
+                    .expect("The child cluster cannot be empty as this has been verified before.")
+            })
+            .collect();
+        Ok(overlaps)
+    }
+}
+
+/// Returns the stability of the child cluster compared to the parent clusters.
+/// Returns an error if the child cluster is empty.
+/// The stability is defined as the sum of the squared relative overlaps.
+///
+/// # Parameters
+///
+/// * `cluster_parent` - the parent cluster to use as reference
+/// * `cluster_child` - the child cluster calculate the stability from
+pub fn cluster_stability<A: Borrow<HashSet<usize>>, B: Borrow<HashSet<usize>>>(
+    clusters_parent: &[A],
+    cluster_child: B,
+) -> Result<f64, String> {
+    let relative_overlaps = cluster_overlaps_relative(clusters_parent, cluster_child)?;
+    Ok(relative_overlaps
+        .into_iter()
+        .map(|overlap| overlap.powi(2))
+        .sum())
+}
+
+/// Returns the weighted arithmetic mean of the values.
+/// Returns an error if there are no values or if the weights do not sum up to a positive number.
+///
+/// # Parameters
+///
+/// * `values` - the values to average
+/// * `weights` - the weight of each value
+pub fn weighted_mean(values: &[f64], weights: &[f64]) -> Result<f64, &'static str> {
+    if values.is_empty() || values.len() != weights.len() {
+        return Err("There must be exactly one weight per value.");
+    }
+    let total_weight: f64 = weights.iter().sum();
+    if total_weight <= 0.0 {
+        return Err("The weights must sum up to a positive number.");
+    }
+    Ok(values
+        .iter()
+        .zip(weights)
+        .map(|(value, weight)| value * weight)
+        .sum::<f64>()
+        / total_weight)
+}
+
+/// Returns the median of the values.
+/// Returns an error if there are no values.
+///
+/// # Parameters
+///
+/// * `values` - the values to calculate the median of
+pub fn median(values: &[f64]) -> Result<f64, &'static str> {
+    let sorted = sorted_values(values)?;
+    let middle = sorted.len() / 2;
+    if sorted.len() % 2 == 0 {
+        Ok((sorted[middle - 1] + sorted[middle]) / 2.0)
+    } else {
+        Ok(sorted[middle])
+    }
+}
+
+/// Returns the mean of the values after removing the specified proportion of
+/// the lowest and the highest values each.
+/// Returns an error if there are no values or if the proportion is not in the interval `[0, 0.5)`.
+///
+/// # Parameters
+///
+/// * `values` - the values to average
+/// * `proportion` - the proportion of values to remove from each end
+pub fn trimmed_mean(values: &[f64], proportion: f64) -> Result<f64, &'static str> {
+    if !(0.0..0.5).contains(&proportion) {
+        return Err("The trimmed proportion must be in the interval [0, 0.5).");
+    }
+    let sorted = sorted_values(values)?;
+    let trimmed = (sorted.len() as f64 * proportion).floor() as usize;
+    let retained = &sorted[trimmed..(sorted.len() - trimmed)];
+    Ok(retained.iter().sum::<f64>() / retained.len() as f64)
+}
+
+/// Returns the values in ascending order or an error if there are no values.
+///
+/// # Parameters
+///
+/// * `values` - the values to sort
+fn sorted_values(values: &[f64]) -> Result<Vec<f64>, &'static str> {
+    if values.is_empty() {
+        return Err("There must be at least one value.");
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).expect("The values must be valid numbers."));
+    Ok(sorted)
+}
+
+/// Returns the conditional entropy `H(A|B)` (in nats) of the clustering `A` given the clustering `B`.
+/// Both clusterings are expected to partition the same set of cells.
+/// Returns an error if the clusterings do not contain any cells.
+///
+/// # Parameters
+///
+/// * `clusters_a` - the clustering whose remaining uncertainty is measured
+/// * `clusters_b` - the clustering that is known
+pub fn conditional_entropy<A: Borrow<HashSet<usize>>, B: Borrow<HashSet<usize>>>(
+    clusters_a: &[A],
+    clusters_b: &[B],
+) -> Result<f64, &'static str> {
+    let total_cells: usize = clusters_b
+        .iter()
+        .map(|cluster| cluster.borrow().len())
+        .sum();
+    if total_cells == 0 {
+        return Err("The clusterings do not contain any cells.");
+    }
+    let mut entropy = 0.0;
+    for cluster_b in clusters_b {
+        let size_b = cluster_b.borrow().len() as f64;
+        for cluster_a in clusters_a {
+            let overlap = cluster_overlap_absolute(cluster_a.borrow(), cluster_b.borrow());
+            // Empty intersections do not contribute to the entropy as `p * ln(p)` tends to zero.
+            if overlap > 0 {
+                let overlap = overlap as f64;
+                entropy -= (overlap / total_cells as f64) * (overlap / size_b).ln();
+            }
+        }
+    }
+    Ok(entropy)
+}
+
+/// Returns the number of clusters at the knee of the curve within the specified range.
+/// Similar to the Kneedle algorithm, the curve is normalised to the unit square and the knee
+/// is the point furthest from the chord connecting the ends of the curve.
+/// Returns `None` if the curve is not finite, flat or linear within the range.
+///
+/// # Parameters
+///
+/// * `curve` - the stability depending on the number of clusters
+/// * `x_min` - the smallest number of clusters of the range
+/// * `x_max` - the largest number of clusters of the range
+pub fn knee<F: Fn(f64) -> f64>(curve: F, x_min: f64, x_max: f64) -> Option<f64> {
+    let (x, y) = normalised_curve(curve, x_min, x_max)?;
+    let (y_first, y_last) = (y[0], y[y.len() - 1]);
+    let distances = x
+        .iter()
+        .zip(&y)
+        .map(|(x, y)| (y - (y_first + (y_last - y_first) * x)).abs());
+    let (index, distance) = distances
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+    (distance > BEND_TOLERANCE).then(|| x_min + x[index] * (x_max - x_min))
+}
+
+/// Returns the number of clusters at the maximum curvature of the curve within the specified
+/// range. The curvature is calculated on the curve normalised to the unit square, so that
+/// it does not depend on the scale of either axis.
+/// Returns `None` if the curve is not finite, flat or linear within the range.
+///
+/// # Parameters
+///
+/// * `curve` - the stability depending on the number of clusters
+/// * `x_min` - the smallest number of clusters of the range
+/// * `x_max` - the largest number of clusters of the range
+pub fn maximum_curvature<F: Fn(f64) -> f64>(curve: F, x_min: f64, x_max: f64) -> Option<f64> {
+    let (x, y) = normalised_curve(curve, x_min, x_max)?;
+    let step = x[1] - x[0];
+    let (index, curvature) = (1..y.len() - 1)
+        .map(|index| {
+            let first_derivative = (y[index + 1] - y[index - 1]) / (2.0 * step);
+            let second_derivative = (y[index + 1] - 2.0 * y[index] + y[index - 1]) / step.powi(2);
+            (index, second_derivative.abs() / (1.0 + first_derivative.powi(2)).powf(1.5))
+        })
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+    (curvature > BEND_TOLERANCE).then(|| x_min + x[index] * (x_max - x_min))
+}
+
+/// Returns the index of the observation with the largest positive second difference of
+/// the stabilities, which is where the decline of stability flattens the most.
+/// Returns `None` if there are fewer than three observations or the stabilities never
+/// flatten.
+///
+/// # Parameters
+///
+/// * `stabilities` - the stabilities ordered by the number of clusters
+pub fn largest_second_difference(stabilities: &[f64]) -> Option<usize> {
+    stabilities
+        .windows(3)
+        .enumerate()
+        .map(|(index, window)| (index + 1, window[0] - 2.0 * window[1] + window[2]))
+        .filter(|(_, second_difference)| *second_difference > 0.0)
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(index, _)| index)
+}
+
+/// Returns the curve sampled within the specified range and normalised to the unit square.
+/// Returns `None` if the range is empty or the curve is not finite or flat.
+///
+/// # Parameters
+///
+/// * `curve` - the stability depending on the number of clusters
+/// * `x_min` - the smallest number of clusters of the range
+/// * `x_max` - the largest number of clusters of the range
+fn normalised_curve<F: Fn(f64) -> f64>(
+    curve: F,
+    x_min: f64,
+    x_max: f64,
+) -> Option<(Vec<f64>, Vec<f64>)> {
+    if x_max <= x_min {
+        return None;
+    }
+    let x: Vec<f64> = (0..=CURVE_SAMPLING_STEPS)
+        .map(|index| index as f64 / CURVE_SAMPLING_STEPS as f64)
+        .collect();
+    let y: Vec<f64> = x
+        .iter()
+        .map(|x| curve(x_min + x * (x_max - x_min)))
+        .collect();
+    if y.iter().any(|y| !y.is_finite()) {
+        return None;
+    }
+    let y_min = y.iter().copied().fold(f64::INFINITY, f64::min);
+    let y_max = y.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if y_max - y_min <= f64::EPSILON {
+        return None;
+    }
+    let y = y.iter().map(|y| (y - y_min) / (y_max - y_min)).collect();
+    Some((x, y))
+}
+
+/// A family of curves describing the stability of a branch depending on the number of clusters.
+pub trait RegressionModel {
+    /// Returns the name of the model.
+    fn name(&self) -> String;
+
+    /// Returns the formula of a user-defined model or `None` for a predefined model.
+    fn formula(&self) -> Option<String> {
+        None
+    }
+
+    /// Returns the number of parameters of the model.
+    fn number_of_parameters(&self) -> usize;
+
+    /// Returns starting estimates of the parameters derived from the observations.
+    ///
+    /// # Parameters
+    ///
+    /// * `x` - the number of clusters of each observation
+    /// * `y` - the observed stabilities
+    /// * `decay_scale` - the factor the number of clusters the stability decays over is
+    ///   scaled with, where `1.0` gives the default estimates
+    fn initial_estimates(&self, x: &[f64], y: &[f64], decay_scale: f64) -> Vec<f64>;
+
+    /// Returns the numbers of clusters at which the model with the specified parameters
+    /// is singular.
+    ///
+    /// # Parameters
+    ///
+    /// * `parameters` - the parameters of the model
+    fn singularities(&self, _parameters: &[f64]) -> Vec<f64> {
+        Vec::new()
+    }
+
+    /// Evaluates the model for the specified number of clusters.
+    ///
+    /// # Parameters
+    ///
+    /// * `parameters` - the parameters of the model
+    /// * `x` - the number of clusters
+    fn evaluate<'a>(&self, parameters: &[Var<'a>], x: f64) -> Var<'a>;
+}
+
+/// The hyperbola `a / (x*b + c) + d`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Hyperbola;
+
+impl RegressionModel for Hyperbola {
+    fn name(&self) -> String {
+        "hyperbola".to_string()
+    }
+
+    fn number_of_parameters(&self) -> usize {
+        4
+    }
+
+    fn initial_estimates(&self, x: &[f64], y: &[f64], decay_scale: f64) -> Vec<f64> {
+        let observations = ObservationRange::new(x, y);
+        // The pole is placed `decay_scale` clusters below the smallest number of clusters,
+        // so that the curve passes through the first observation and approaches the last.
+        let c = decay_scale - observations.x_min;
+        let d = observations.y_last;
+        let a = (observations.y_first - d).max(MINIMAL_AMPLITUDE_ESTIMATE) * decay_scale;
+        vec![a, 1.0, c, d]
+    }
+
+    fn singularities(&self, parameters: &[f64]) -> Vec<f64> {
+        if parameters[1] != 0.0 {
+            vec![-parameters[2] / parameters[1]]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn evaluate<'a>(&self, parameters: &[Var<'a>], x: f64) -> Var<'a> {
+        parameters[0] / (x * parameters[1] + parameters[2]) + parameters[3]
+    }
+}
+
+/// The exponential decay `a * exp(-b*x) + c`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ExponentialDecay;
+
+impl RegressionModel for ExponentialDecay {
+    fn name(&self) -> String {
+        "exponential-decay".to_string()
+    }
+
+    fn number_of_parameters(&self) -> usize {
+        3
+    }
+
+    fn initial_estimates(&self, x: &[f64], y: &[f64], decay_scale: f64) -> Vec<f64> {
+        let observations = ObservationRange::new(x, y);
+        // The curve decays to about 5% of its amplitude over the scaled observed range.
+        let b = 3.0 / (observations.x_span() * decay_scale);
+        let c = observations.y_last;
+        let a = (observations.y_first - c).max(MINIMAL_AMPLITUDE_ESTIMATE)
+            * (b * observations.x_min).exp();
+        vec![a, b, c]
+    }
+
+    fn evaluate<'a>(&self, parameters: &[Var<'a>], x: f64) -> Var<'a> {
+        parameters[0] * (-(parameters[1] * x)).exp() + parameters[2]
+    }
+}
+
+/// The logistic decay `a / (1 + exp(b*(x - c))) + d`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Logistic;
+
+impl RegressionModel for Logistic {
+    fn name(&self) -> String {
+        "logistic".to_string()
+    }
+
+    fn number_of_parameters(&self) -> usize {
+        4
+    }
+
+    fn initial_estimates(&self, x: &[f64], y: &[f64], decay_scale: f64) -> Vec<f64> {
+        let observations = ObservationRange::new(x, y);
+        // By default the midpoint is placed in the centre of the observed range, which the
+        // transition spans. Slower decays move the midpoint towards finer clusterings.
+        let a = (observations.y_max - observations.y_min).max(MINIMAL_AMPLITUDE_ESTIMATE);
+        let b = 8.0 / (observations.x_span() * decay_scale);
+        let c = observations.x_min + observations.x_span() * decay_scale / (1.0 + decay_scale);
+        vec![a, b, c, observations.y_min]
+    }
+
+    fn evaluate<'a>(&self, parameters: &[Var<'a>], x: f64) -> Var<'a> {
+        parameters[0] / ((parameters[1] * (x - parameters[2])).exp() + 1.0) + parameters[3]
+    }
+}
+
+/// The power law `a * x^(-b) + c`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PowerLaw;
+
+impl RegressionModel for PowerLaw {
+    fn name(&self) -> String {
+        "power-law".to_string()
+    }
+
+    fn number_of_parameters(&self) -> usize {
+        3
+    }
+
+    fn initial_estimates(&self, x: &[f64], y: &[f64], decay_scale: f64) -> Vec<f64> {
+        let observations = ObservationRange::new(x, y);
+        let b = 1.0 / decay_scale;
+        let c = observations.y_last;
+        let a =
+            (observations.y_first - c).max(MINIMAL_AMPLITUDE_ESTIMATE) * observations.x_min.powf(b);
+        vec![a, b, c]
+    }
+
+    fn evaluate<'a>(&self, parameters: &[Var<'a>], x: f64) -> Var<'a> {
+        parameters[0] * (-(parameters[1] * x.ln())).exp() + parameters[2]
+    }
+}
+
+/// The regression models that can be selected by name.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RegressionModelType {
+    /// The hyperbola `a / (x*b + c) + d`.
+    Hyperbola,
+    /// The exponential decay `a * exp(-b*x) + c`.
+    ExponentialDecay,
+    /// The logistic decay `a / (1 + exp(b*(x - c))) + d`.
+    Logistic,
+    /// The power law `a * x^(-b) + c`.
+    PowerLaw,
+}
+
+impl RegressionModelType {
+    /// Returns the model of this type.
+    pub fn model(&self) -> Box<dyn RegressionModel> {
+        match self {
+            RegressionModelType::Hyperbola => Box::new(Hyperbola),
+            RegressionModelType::ExponentialDecay => Box::new(ExponentialDecay),
+            RegressionModelType::Logistic => Box::new(Logistic),
+            RegressionModelType::PowerLaw => Box::new(PowerLaw),
+        }
+    }
+}
+
+/// The regression model to fit, either selected by name or defined by a formula.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ModelSpecification {
+    /// A predefined model.
+    Named(RegressionModelType),
+    /// A user-defined formula.
+    Formula(FormulaModel),
+}
+
+impl ModelSpecification {
+    /// Returns the specified model.
+    pub fn model(&self) -> Box<dyn RegressionModel> {
+        match self {
+            ModelSpecification::Named(model_type) => model_type.model(),
+            ModelSpecification::Formula(formula) => Box::new(formula.clone()),
+        }
+    }
+}
+
+/// The range of observations used to derive starting estimates of model parameters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ObservationRange {
+    /// The smallest number of clusters.
+    x_min: f64,
+    /// The largest number of clusters.
+    x_max: f64,
+    /// The stability at the smallest number of clusters.
+    y_first: f64,
+    /// The stability at the largest number of clusters.
+    y_last: f64,
+    /// The smallest stability.
+    y_min: f64,
+    /// The largest stability.
+    y_max: f64,
+}
+
+impl ObservationRange {
+    /// Summarises the specified observations. Without observations a range of
+    /// `1` to `2` clusters with perfect stability is assumed.
+    ///
+    /// # Parameters
+    ///
+    /// * `x` - the number of clusters of each observation
+    /// * `y` - the observed stabilities
+    fn new(x: &[f64], y: &[f64]) -> Self {
+        let mut range = Self {
+            x_min: f64::INFINITY,
+            x_max: f64::NEG_INFINITY,
+            y_first: 1.0,
+            y_last: 1.0,
+            y_min: f64::INFINITY,
+            y_max: f64::NEG_INFINITY,
+        };
+        for (x, y) in x.iter().zip(y) {
+            if *x < range.x_min {
+                range.x_min = *x;
+                range.y_first = *y;
+            }
+            if *x > range.x_max {
+                range.x_max = *x;
+                range.y_last = *y;
+            }
+            range.y_min = range.y_min.min(*y);
+            range.y_max = range.y_max.max(*y);
+        }
+        if !range.x_min.is_finite() {
+            range.x_min = 1.0;
+            range.x_max = 2.0;
+            range.y_min = 1.0;
+            range.y_max = 1.0;
+        }
+        range
+    }
+
+    /// Returns the span of the number of clusters, which is at least `1`.
+    fn x_span(&self) -> f64 {
+        (self.x_max - self.x_min).max(1.0)
+    }
+}
+
+#[derive(CopyGetters, Clone, Debug, PartialEq, Deserialize, Serialize)]
+/// The residual of a single observation of a regression.
+pub struct RegressionResidual {
+    /// The number of clusters.
+    #[getset(get_copy = "pub")]
+    clusters: f64,
+    /// The observed stability.
+    #[getset(get_copy = "pub")]
+    observed: f64,
+    /// The stability predicted by the regression.
+    #[getset(get_copy = "pub")]
+    predicted: f64,
+    /// The difference between the observed and the predicted stability.
+    #[getset(get_copy = "pub")]
+    residual: f64,
+    /// The weight of the observation in the fit, where robust fits down-weight
+    /// outliers below `1.0`.
+    #[getset(get_copy = "pub")]
+    weight: f64,
+}
+
+#[derive(CopyGetters, Getters, Clone, Debug, PartialEq, Deserialize, Serialize)]
+/// The goodness of fit of a regression.
+pub struct RegressionDiagnostics {
+    /// The standard errors of the fitted parameters or `None` if a standard error
+    /// cannot be determined.
+    #[getset(get = "pub")]
+    standard_errors: Vec<Option<f64>>,
+    /// The residuals of all observations.
+    #[getset(get = "pub")]
+    residuals: Vec<RegressionResidual>,
+    /// The weighted coefficient of determination, which compares the weighted sum of squared
+    /// residuals to the weighted squared deviations from the weighted mean, or `None` if the
+    /// observations do not vary.
+    #[getset(get_copy = "pub")]
+    r_squared: Option<f64>,
+    /// The root of the weighted mean of the squared residuals or `None` if there are
+    /// no observations.
+    #[getset(get_copy = "pub")]
+    root_mean_squared_error: Option<f64>,
+    /// The Akaike information criterion or `None` if it cannot be determined.
+    /// It is only determined for the squared error loss, whose fit maximises the
+    /// likelihood of normally distributed errors.
+    #[getset(get_copy = "pub")]
+    aic: Option<f64>,
+    /// The Akaike information criterion corrected for small samples or `None`
+    /// if there are too few observations or the loss is robust.
+    #[getset(get_copy = "pub")]
+    aicc: Option<f64>,
+    /// The Bayesian information criterion or `None` if it cannot be determined
+    /// or the loss is robust.
+    #[getset(get_copy = "pub")]
+    bic: Option<f64>,
+    /// The number of iterations the optimiser needed to reach the fitted parameters
+    /// from the selected starting point.
+    #[getset(get_copy = "pub")]
+    iterations: usize,
+    /// The maximum norm of the gradient `Jᵀr` of the weighted residuals `r` at the fitted
+    /// parameters, which the optimiser compares to the gradient tolerance.
+    #[getset(get_copy = "pub")]
+    gradient_norm: f64,
+    /// The number of starting points the optimiser was run from.
+    #[getset(get_copy = "pub")]
+    starts: usize,
+    /// The number of starting points, whose fit was rejected due to a singularity within
+    /// the observed range of clusters.
+    #[getset(get_copy = "pub")]
+    rejected_starts: usize,
+    /// If the fitted curve is singular within the observed range of clusters, which is
+    /// only the case if the fits from all starting points are singular.
+    #[getset(get_copy = "pub")]
+    singular: bool,
+    /// If the fitted parameters are finite and either the gradient at the fitted parameters
+    /// is within its tolerance or the optimiser stopped due to its tolerance criteria
+    /// before reaching the iteration limit.
+    /// Robust fits have additionally converged only if their weights settled within the
+    /// maximum number of reweighting iterations.
+    #[getset(get_copy = "pub")]
+    converged: bool,
+}
+
+impl RegressionDiagnostics {
+    /// Returns a description of why the fit is poor or `None` if the fit is acceptable.
+    pub fn fit_warning(&self) -> Option<String> {
+        let mut problems = Vec::new();
+        if !self.converged {
+            problems.push(format!(
+                "the optimiser did not converge within {} iterations",
+                self.iterations
+            ));
+        }
+        if self.singular {
+            problems.push("the fitted curve is singular within the observed range".to_string());
+        }
+        if let Some(r_squared) = self
+            .r_squared
+            .filter(|r_squared| *r_squared < POOR_FIT_R_SQUARED)
+        {
+            problems
+                .push(format!("the fit explains only {:.1}% of the variance", r_squared * 100.0));
+        }
+        if problems.is_empty() {
+            None
+        } else {
+            Some(format!("The stability regression is poor: {}.", problems.join(" and ")))
+        }
+    }
+}
+
+/// The weightings of observed transition stabilities in regressions.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ObservationWeighting {
+    /// All transitions are weighted equally.
+    #[default]
+    Uniform,
+    /// Transitions are weighted by the number of child clusters.
+    ChildClusters,
+    /// Transitions are weighted by the number of cells in the child clusters.
+    Cells,
+    /// Transitions are weighted by the inverse variance of the mean of their
+    /// child cluster stabilities.
+    InverseVariance,
+}
+
+impl ObservationWeighting {
+    /// Returns the observed stabilities of the branch weighted accordingly.
+    /// Returns an error if the transitions of the branch cannot be computed.
+    ///
+    /// # Parameters
+    ///
+    /// * `branch` - the branch to extract the observations from
+    /// * `resolutions` - the clusterings the branch has been built from
+    pub fn observations(
+        &self,
+        branch: &[Rc<ResolutionNode>],
+        resolutions: &[ResolutionData],
+    ) -> Result<StabilityObservations, String> {
+        let mut observations = StabilityObservations::new(branch);
+        if *self == ObservationWeighting::Uniform {
+            return Ok(observations);
+        }
+        let transitions = branch_transitions(branch, resolutions)?;
+        let weights: Vec<f64> = observations
+            .clusters
+            .iter()
+            .map(|clusters| {
+                transitions
+                    .iter()
+                    .find(|transition| transition.clusters_child() as f64 == *clusters)
+                    .map(|transition| self.weight(transition))
+                    .ok_or_else(|| {
+                        format!(
+                            "The branch does not contain a transition to {} clusters.",
+                            clusters
+                        )
+                    })
+            })
+            .collect::<Result<Vec<f64>, String>>()?;
+        // Weights are normalised to a mean of 1, so that residuals keep their scale.
+        let mean_weight = weights.iter().sum::<f64>() / weights.len() as f64;
+        observations.weights = weights.iter().map(|weight| weight / mean_weight).collect();
+        observations.weighting = *self;
+        Ok(observations)
+    }
+
+    /// Returns the unnormalised weight of the transition.
+    ///
+    /// # Parameters
+    ///
+    /// * `transition` - the transition to weight
+    fn weight(&self, transition: &ClusterStabilityData) -> f64 {
+        match self {
+            ObservationWeighting::Uniform => 1.0,
+            ObservationWeighting::ChildClusters => transition.clusters_child() as f64,
+            ObservationWeighting::Cells => {
+                transition.child_cluster_sizes().iter().sum::<usize>() as f64
+            },
+            ObservationWeighting::InverseVariance => {
+                let stabilities = transition.stabilities();
+                let n = stabilities.len() as f64;
+                let mean = stabilities.iter().sum::<f64>() / n;
+                let variance = stabilities
+                    .iter()
+                    .map(|stability| (stability - mean).powi(2))
+                    .sum::<f64>()
+                    / (n - 1.0).max(1.0);
+                // The variance of the mean is bounded from below, so that transitions
+                // with identical child cluster stabilities do not dominate the fit.
+                1.0 / (variance / n).max(MINIMAL_STABILITY_VARIANCE)
+            },
+        }
+    }
+}
+
+#[derive(CopyGetters, Getters, Clone, Debug, PartialEq)]
+/// The observed transition stabilities of a branch a regression is fitted to.
+pub struct StabilityObservations {
+    /// The number of clusters of each observation.
+    #[getset(get = "pub")]
+    clusters: Vec<f64>,
+    /// The observed stabilities.
+    #[getset(get = "pub")]
+    stabilities: Vec<f64>,
+    /// The weight of each observation.
+    #[getset(get = "pub")]
+    weights: Vec<f64>,
+    /// The weighting used to derive the weights.
+    #[getset(get_copy = "pub")]
+    weighting: ObservationWeighting,
+}
+
+impl StabilityObservations {
+    /// Returns the uniformly weighted number of clusters and stability of all branch nodes
+    /// with a parent.
+    ///
+    /// # Parameters
+    ///
+    /// * `branch` - the branch to extract the observations from
+    pub fn new(branch: &[Rc<ResolutionNode>]) -> Self {
+        let (clusters, stabilities): (Vec<f64>, Vec<f64>) = branch
+            .iter()
+            .filter_map(|node| {
+                node.optimal_stability()
+                    .map(|stability| (node.number_of_clusters() as f64, stability))
+            })
+            .unzip();
+        Self {
+            weights: vec![1.0; clusters.len()],
+            clusters,
+            stabilities,
+            weighting: ObservationWeighting::Uniform,
+        }
+    }
+
+    /// Returns the number of observations.
+    pub fn len(&self) -> usize {
+        self.clusters.len()
+    }
+
+    /// Returns `true` if there are no observations.
+    pub fn is_empty(&self) -> bool {
+        self.clusters.is_empty()
+    }
+}
+
+/// The loss functions used to fit regression models.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LossFunction {
+    /// Ordinary least squares.
+    #[default]
+    SquaredError,
+    /// The Huber loss, which linearly down-weights residuals beyond 1.345 robust
+    /// standard deviations.
+    Huber,
+    /// The Tukey bisquare loss, which smoothly down-weights residuals and ignores
+    /// residuals beyond 4.685 robust standard deviations.
+    Tukey,
+}
+
+impl LossFunction {
+    /// Returns `true` if the loss down-weights large residuals by iteratively reweighted
+    /// least squares.
+    pub fn is_robust(&self) -> bool {
+        *self != LossFunction::SquaredError
+    }
+
+    /// Returns the weights of iteratively reweighted least squares for the specified residuals.
+    /// The residuals are standardised by their median absolute deviation. Returns `None` if
+    /// the loss is not robust or the residuals cannot be standardised.
+    ///
+    /// # Parameters
+    ///
+    /// * `residuals` - the residuals of the current fit
+    fn robust_weights(&self, residuals: &[f64]) -> Option<Vec<f64>> {
+        let absolute_residuals: Vec<f64> =
+            residuals.iter().map(|residual| residual.abs()).collect();
+        // The median absolute deviation is a consistent estimator of the standard deviation
+        // of normally distributed errors if scaled by `1 / 0.6745`.
+        let scale = median(&absolute_residuals).ok()? / 0.6745;
+        if scale <= f64::EPSILON {
+            return None;
+        }
+        let standardised_residuals = absolute_residuals.iter().map(|residual| residual / scale);
+        match self {
+            LossFunction::SquaredError => None,
+            LossFunction::Huber => Some(
+                standardised_residuals
+                    .map(|residual| {
+                        if residual <= HUBER_TUNING_CONSTANT {
+                            1.0
+                        } else {
+                            HUBER_TUNING_CONSTANT / residual
+                        }
+                    })
+                    .collect(),
+            ),
+            LossFunction::Tukey => Some(
+                standardised_residuals
+                    .map(|residual| {
+                        if residual < TUKEY_TUNING_CONSTANT {
+                            (1.0 - (residual / TUKEY_TUNING_CONSTANT).powi(2)).powi(2)
+                        } else {
+                            0.0
+                        }
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[derive(CopyGetters, Clone, Copy, Debug, PartialEq)]
+/// The settings used to fit regression models.
+pub struct RegressionSettings {
+    /// The loss function minimised by the fit.
+    #[getset(get_copy = "pub")]
+    loss: LossFunction,
+    /// The fraction of observations used for each local fit of LOESS.
+    #[getset(get_copy = "pub")]
+    loess_span: f64,
+    /// The maximum number of iterations of the optimiser.
+    #[getset(get_copy = "pub")]
+    maximum_iterations: usize,
+    /// The tolerance of the gradient of the squared residuals, at which the optimiser stops.
+    #[getset(get_copy = "pub")]
+    gradient_tolerance: f64,
+    /// The tolerance of the relative change of parameters, at which the optimiser stops.
+    #[getset(get_copy = "pub")]
+    step_tolerance: f64,
+    /// The number of starting points the optimiser is run from.
+    #[getset(get_copy = "pub")]
+    starts: usize,
+}
+
+impl RegressionSettings {
+    /// Creates new regression settings.
+    ///
+    /// # Parameters
+    ///
+    /// * `loss` - the loss function minimised by the fit
+    /// * `loess_span` - the fraction of observations used for each local fit of LOESS
+    /// * `maximum_iterations` - the maximum number of iterations of the optimiser
+    /// * `gradient_tolerance` - the tolerance of the gradient of the squared residuals
+    /// * `step_tolerance` - the tolerance of the relative change of parameters
+    /// * `starts` - the number of starting points the optimiser is run from
+    pub fn new(
+        loss: LossFunction,
+        loess_span: f64,
+        maximum_iterations: usize,
+        gradient_tolerance: f64,
+        step_tolerance: f64,
+        starts: usize,
+    ) -> Self {
+        Self {
+            loss,
+            loess_span,
+            maximum_iterations,
+            gradient_tolerance,
+            step_tolerance,
+            starts,
+        }
+    }
+}
+
+impl Default for RegressionSettings {
+    fn default() -> Self {
+        Self::new(
+            LossFunction::default(),
+            DEFAULT_LOESS_SPAN,
+            DEFAULT_MAXIMUM_ITERATIONS,
+            DEFAULT_GRADIENT_TOLERANCE,
+            DEFAULT_STEP_TOLERANCE,
+            DEFAULT_STARTS,
+        )
+    }
+}
+
+/// A curve describing the stability depending on the number of clusters.
+pub trait StabilityCurve {
+    /// Returns the predicted stability for the specified number of clusters.
+    ///
+    /// # Parameters
+    ///
+    /// * `x` - the number of clusters
+    fn predict(&self, x: f64) -> f64;
+
+    /// Returns the number of clusters within the specified range, at which the fitted curve
+    /// drops below the threshold without exceeding it again within the range.
+    /// Returns `None` if the curve is below or above the threshold across the whole range
+    /// or only crosses it at a singularity.
+    ///
+    /// # Parameters
+    ///
+    /// * `threshold` - the stability threshold
+    /// * `x_min` - the smallest number of clusters of the range
+    /// * `x_max` - the largest number of clusters of the range
+    fn threshold_crossing(&self, threshold: f64, x_min: f64, x_max: f64) -> Option<f64> {
+        let above = |x: f64| self.predict(x) >= threshold;
+        let step = (x_max - x_min) / CROSSING_SEARCH_STEPS as f64;
+        let grid_point = |index: usize| x_min + index as f64 * step;
+        // The last sign change is bracketed on a grid and refined by bisection.
+        let last_above = (0..=CROSSING_SEARCH_STEPS)
+            .rev()
+            .find(|index| above(grid_point(*index)))?;
+        if last_above == CROSSING_SEARCH_STEPS {
+            return None;
+        }
+        let (mut lower, mut upper) = (grid_point(last_above), grid_point(last_above + 1));
+        for _ in 0..CROSSING_BISECTION_STEPS {
+            let middle = (lower + upper) / 2.0;
+            if above(middle) {
+                lower = middle;
+            } else {
+                upper = middle;
+            }
+        }
+        let crossing = (lower + upper) / 2.0;
+        // A sign change at a pole of the curve is not a crossing.
+        if (self.predict(crossing) - threshold).abs() <= CROSSING_TOLERANCE {
+            Some(crossing)
+        } else {
+            None
+        }
+    }
+}
+
+/// The estimated parameters of a model and their standard errors.
+type ParameterEstimates = (Vec<f64>, Vec<Option<f64>>);
+
+/// A regression of cluster stability data.
+#[derive(CopyGetters, Getters, Deserialize, Serialize)]
+pub struct ClusterStabilityRegression {
+    /// The model fitted to the data, which is stored by its name if it is predefined
+    /// or by its formula if it is user-defined.
+    #[getset(get = "pub")]
+    #[serde(
+        serialize_with = "serialize_model",
+        deserialize_with = "deserialize_model"
+    )]
+    model: Box<dyn RegressionModel>,
+    /// The loss function minimised by the fit.
+    #[getset(get_copy = "pub")]
+    loss: LossFunction,
+    /// The weighting of the observations.
+    #[getset(get_copy = "pub")]
+    weighting: ObservationWeighting,
+    /// The fitted parameters of the model.
+    #[getset(get = "pub")]
+    parameters: Vec<f64>,
+    /// The goodness of fit.
+    #[getset(get = "pub")]
+    diagnostics: RegressionDiagnostics,
+}
+
+impl ClusterStabilityRegression {
+    /// Fits the specified model to the observed stabilities.
+    ///
+    /// # Parameters
+    ///
+    /// * `observations` - the observed stabilities to fit the model to
+    /// * `model` - the model to fit
+    /// * `settings` - the settings used to fit the model
+    pub fn new(
+        observations: &StabilityObservations,
+        model: Box<dyn RegressionModel>,
+        settings: &RegressionSettings,
+    ) -> Self {
+        let x = observations.clusters();
+        let y = observations.stabilities();
+        let observation_weights = observations.weights();
+        let mut weights = observation_weights.clone();
+        let range = ObservationRange::new(x, y);
+        // The optimiser is run from every starting point and the non-singular fit with the
+        // smallest weighted sum of squared residuals is selected.
+        // Models with fixed starting values yield the same starting point repeatedly,
+        // which is only fitted once.
+        let mut starting_points: Vec<Vec<f64>> = Vec::new();
+        for start in 0..settings.starts().max(1) {
+            let initial_parameters = model.initial_estimates(x, y, Self::decay_scale(start));
+            if !starting_points.contains(&initial_parameters) {
+                starting_points.push(initial_parameters);
+            }
+        }
+        let starts = starting_points.len();
+        let mut rejected_starts = 0;
+        let mut best_fit: Option<(bool, f64, Vec<f64>, ParameterEstimates)> = None;
+        for initial_parameters in starting_points {
+            let estimates = Self::estimate_parameters(
+                x,
+                y,
+                &weights,
+                model.as_ref(),
+                &initial_parameters,
+                settings,
+            );
+            let parameters = &estimates.0;
+            let singular = Self::is_singular(model.as_ref(), parameters, x, &range);
+            if singular {
+                rejected_starts += 1;
+            }
+            let residual_sum_of_squares = Some(Self::weighted_residual_sum_of_squares(
+                model.as_ref(),
+                parameters,
+                x,
+                y,
+                &weights,
+            ))
+            .filter(|sum| sum.is_finite())
+            .unwrap_or(f64::INFINITY);
+            let improves = best_fit
+                .as_ref()
+                .map(|(best_singular, best_sum, ..)| {
+                    (singular, residual_sum_of_squares) < (*best_singular, *best_sum)
+                })
+                .unwrap_or(true);
+            if improves {
+                best_fit = Some((singular, residual_sum_of_squares, initial_parameters, estimates));
+            }
+        }
+        let (_, _, mut initial_parameters, (mut parameters, mut standard_errors)) =
+            best_fit.expect("The optimiser is run from at least one starting point.");
+        // Robust fits are refined by iteratively reweighted least squares,
+        // where the robust weights scale the weights of the observations.
+        let mut weights_settled = true;
+        for _ in 0..MAXIMUM_ROBUST_ITERATIONS {
+            let residuals: Vec<f64> = x
+                .iter()
+                .zip(y)
+                .zip(observation_weights)
+                .map(|((x, y), weight)| {
+                    (y - Self::evaluate(model.as_ref(), &parameters, *x)) * weight.sqrt()
+                })
+                .collect();
+            let Some(robust_weights) = settings.loss().robust_weights(&residuals) else {
+                // The loss is not robust or all residuals vanish, so the weights are final.
+                weights_settled = true;
+                break;
+            };
+            let robust_weights: Vec<f64> = robust_weights
+                .iter()
+                .zip(observation_weights)
+                .map(|(robust_weight, weight)| robust_weight * weight)
+                .collect();
+            let weight_change = weights
+                .iter()
+                .zip(&robust_weights)
+                .map(|(weight, robust_weight)| (weight - robust_weight).abs())
+                .fold(0.0, f64::max);
+            weights = robust_weights;
+            initial_parameters = parameters;
+            (parameters, standard_errors) = Self::estimate_parameters(
+                x,
+                y,
+                &weights,
+                model.as_ref(),
+                &initial_parameters,
+                settings,
+            );
+            weights_settled = weight_change < ROBUST_WEIGHT_TOLERANCE;
+            if weights_settled {
+                break;
+            }
+        }
+        let iterations = Self::iterations(
+            x,
+            y,
+            &weights,
+            model.as_ref(),
+            &initial_parameters,
+            &parameters,
+            settings,
+        );
+        let gradient_norm = Self::gradient_norm(x, y, &weights, model.as_ref(), &parameters);
+        // A step accepted at the iteration limit can still satisfy the gradient tolerance.
+        let converged = weights_settled
+            && parameters.iter().all(|parameter| parameter.is_finite())
+            && (gradient_norm <= settings.gradient_tolerance()
+                || Self::is_settled(
+                    x,
+                    y,
+                    &weights,
+                    model.as_ref(),
+                    &initial_parameters,
+                    &parameters,
+                    settings,
+                ));
+        let singular = Self::is_singular(model.as_ref(), &parameters, x, &range);
+        let mut regression = Self {
+            model,
+            loss: settings.loss(),
+            weighting: observations.weighting(),
+            parameters,
+            diagnostics: RegressionDiagnostics {
+                standard_errors,
+                residuals: Vec::new(),
+                r_squared: None,
+                root_mean_squared_error: None,
+                aic: None,
+                aicc: None,
+                bic: None,
+                iterations,
+                gradient_norm,
+                starts,
+                rejected_starts,
+                singular,
+                converged,
+            },
+        };
+        regression.diagnose(x, y, &weights);
+        regression
+    }
+
+    /// Returns `true` if there are more observations than the model has parameters, so that
+    /// the model and the variance of its residuals can be estimated.
+    ///
+    /// # Parameters
+    ///
+    /// * `observations` - the observed stabilities
+    /// * `model` - the model to fit
+    pub fn is_determined(
+        observations: &StabilityObservations,
+        model: &dyn RegressionModel,
+    ) -> bool {
+        observations.len() > model.number_of_parameters()
+    }
+
+    /// Returns the factor the decay of the starting estimates is scaled with for the
+    /// specified starting point. The starting points alternate between faster and slower
+    /// decays: `1`, `1/2`, `2`, `1/4`, `4` and so on.
+    ///
+    /// # Parameters
+    ///
+    /// * `start` - the index of the starting point
+    fn decay_scale(start: usize) -> f64 {
+        let exponent = start.div_ceil(2) as i32;
+        if start % 2 == 1 {
+            2f64.powi(-exponent)
+        } else {
+            2f64.powi(exponent)
+        }
+    }
+
+    /// Returns `true` if the model with the specified parameters is not finite at any
+    /// observation or has a singularity within the observed range of clusters.
+    ///
+    /// # Parameters
+    ///
+    /// * `model` - the fitted model
+    /// * `parameters` - the fitted parameters
+    /// * `x` - the number of clusters of each observation
+    /// * `range` - the range of the observations
+    fn is_singular(
+        model: &dyn RegressionModel,
+        parameters: &[f64],
+        x: &[f64],
+        range: &ObservationRange,
+    ) -> bool {
+        parameters.iter().any(|parameter| !parameter.is_finite())
+            || x.iter()
+                .any(|x| !Self::evaluate(model, parameters, *x).is_finite())
+            || model
+                .singularities(parameters)
+                .iter()
+                .any(|singularity| (range.x_min..=range.x_max).contains(singularity))
+    }
+
+    /// Returns the weighted sum of squared residuals of the model with the specified parameters.
+    ///
+    /// # Parameters
+    ///
+    /// * `model` - the fitted model
+    /// * `parameters` - the fitted parameters
+    /// * `x` - the number of clusters of each observation
+    /// * `y` - the observed stabilities
+    /// * `weights` - the weight of each observation
+    fn weighted_residual_sum_of_squares(
+        model: &dyn RegressionModel,
+        parameters: &[f64],
+        x: &[f64],
+        y: &[f64],
+        weights: &[f64],
+    ) -> f64 {
+        x.iter()
+            .zip(y)
+            .zip(weights)
+            .map(|((x, y), weight)| weight * (y - Self::evaluate(model, parameters, *x)).powi(2))
+            .sum()
+    }
+
+    /// Calculates the weighted least squares parameter estimates and their standard errors.
+    ///
+    /// # Parameters
+    ///
+    /// * `x` - the number of clusters of each observation
+    /// * `y` - the observed stabilities
+    /// * `weights` - the weight of each observation
+    /// * `model` - the model to fit
+    /// * `initial_parameters` - the parameters to start the optimisation from
+    /// * `settings` - the settings of the optimiser
+    fn estimate_parameters(
+        x: &[f64],
+        y: &[f64],
+        weights: &[f64],
+        model: &dyn RegressionModel,
+        initial_parameters: &[f64],
+        settings: &RegressionSettings,
+    ) -> ParameterEstimates {
+        let (inferred_parameters, covariance) = Self::optimise(
+            x,
+            y,
+            weights,
+            model,
+            initial_parameters,
+            settings,
+            settings.maximum_iterations(),
+        );
+        let standard_errors = covariance
+            .diag()
+            .iter()
+            .map(|variance| Some(variance.sqrt()).filter(|error| error.is_finite()))
+            .collect();
+        (inferred_parameters.to_vec(), standard_errors)
+    }
+
+    /// Runs the optimiser for at most the specified number of iterations and returns
+    /// the inferred parameters and their covariance.
+    ///
+    /// # Parameters
+    ///
+    /// * `x` - the number of clusters of each observation
+    /// * `y` - the observed stabilities
+    /// * `weights` - the weight of each observation
+    /// * `model` - the model to fit
+    /// * `initial_parameters` - the parameters to start the optimisation from
+    /// * `settings` - the settings of the optimiser
+    /// * `maximum_iterations` - the maximum number of iterations of the optimiser
+    fn optimise(
+        x: &[f64],
+        y: &[f64],
+        weights: &[f64],
+        model: &dyn RegressionModel,
+        initial_parameters: &[f64],
+        settings: &RegressionSettings,
+        maximum_iterations: usize,
+    ) -> (Vector, Matrix) {
+        // The optimiser minimises unweighted residuals, so observations and predictions are
+        // scaled by the square root of their weights. As the optimiser only passes the
+        // independent variable to the model, observations are identified by their index.
+        let indices: Vector = (0..x.len()).map(|index| index as f64).collect();
+        let weight_roots: Vec<f64> = weights.iter().map(|weight| weight.sqrt()).collect();
+        let weighted_y: Vector = y
+            .iter()
+            .zip(&weight_roots)
+            .map(|(y, weight_root)| y * weight_root)
+            .collect();
+        // Sets up and runs the non-linear regression.
+        let lm = LM::new(settings.gradient_tolerance(), settings.step_tolerance(), INITIAL_DAMPING);
+        lm.optimize(
+            |parameters, data| {
+                let index = Self::observation_index(data);
+                Self::model_function(model, parameters, &[&[x[index]]]) * weight_roots[index]
+            },
+            initial_parameters,
+            &[&indices, &weighted_y],
+            maximum_iterations,
+        )
+    }
+
+    /// Returns the number of iterations the optimiser needs to reach the fitted parameters
+    /// from the initial parameters.
+    /// The optimiser does not report its iterations, but is deterministic, so a run with a
+    /// lower iteration limit performs the same first iterations. As rejected steps leave the
+    /// parameters unchanged, the iterations are the smallest limit at which the optimiser
+    /// returns the fitted parameters, which is found by bisection.
+    ///
+    /// # Parameters
+    ///
+    /// * `x` - the number of clusters of each observation
+    /// * `y` - the observed stabilities
+    /// * `weights` - the weight of each observation
+    /// * `model` - the fitted model
+    /// * `initial_parameters` - the parameters the optimisation was started from
+    /// * `parameters` - the fitted parameters
+    /// * `settings` - the settings of the optimiser
+    fn iterations(
+        x: &[f64],
+        y: &[f64],
+        weights: &[f64],
+        model: &dyn RegressionModel,
+        initial_parameters: &[f64],
+        parameters: &[f64],
+        settings: &RegressionSettings,
+    ) -> usize {
+        let (mut lower, mut upper) = (0, settings.maximum_iterations());
+        while lower < upper {
+            let limit = (lower + upper) / 2;
+            let (limited_parameters, _) =
+                Self::optimise(x, y, weights, model, initial_parameters, settings, limit);
+            if Self::are_identical(&limited_parameters, parameters) {
+                upper = limit;
+            } else {
+                lower = limit + 1;
+            }
+        }
+        upper
+    }
+
+    /// Returns `true` if the optimiser keeps the fitted parameters, when it is granted
+    /// additional iterations.
+    /// The optimiser does not report why it stopped. If it stopped due to its tolerance criteria,
+    /// the additional iterations are not performed. Otherwise, the parameters are only kept if
+    /// all additional steps are rejected, each of which increases the damping of the steps
+    /// until they are within the step tolerance.
+    ///
+    /// # Parameters
+    ///
+    /// * `x` - the number of clusters of each observation
+    /// * `y` - the observed stabilities
+    /// * `weights` - the weight of each observation
+    /// * `model` - the fitted model
+    /// * `initial_parameters` - the parameters the optimisation was started from
+    /// * `parameters` - the fitted parameters
+    /// * `settings` - the settings of the optimiser
+    fn is_settled(
+        x: &[f64],
+        y: &[f64],
+        weights: &[f64],
+        model: &dyn RegressionModel,
+        initial_parameters: &[f64],
+        parameters: &[f64],
+        settings: &RegressionSettings,
+    ) -> bool {
+        let (continued_parameters, _) = Self::optimise(
+            x,
+            y,
+            weights,
+            model,
+            initial_parameters,
+            settings,
+            settings.maximum_iterations() + SETTLING_ITERATIONS,
+        );
+        Self::are_identical(&continued_parameters, parameters)
+    }
+
+    /// Returns `true` if both sets of parameters are bitwise identical.
+    ///
+    /// # Parameters
+    ///
+    /// * `parameters_a` - the first set of parameters
+    /// * `parameters_b` - the second set of parameters
+    fn are_identical(parameters_a: &[f64], parameters_b: &[f64]) -> bool {
+        parameters_a.len() == parameters_b.len()
+            && parameters_a
+                .iter()
+                .zip(parameters_b)
+                .all(|(a, b)| a.to_bits() == b.to_bits())
+    }
+
+    /// Returns the maximum norm of the gradient `Jᵀr` of the weighted residuals `r` at the
+    /// specified parameters, which is infinite if any component of the gradient is not a number.
+    ///
+    /// # Parameters
+    ///
+    /// * `x` - the number of clusters of each observation
+    /// * `y` - the observed stabilities
+    /// * `weights` - the weight of each observation
+    /// * `model` - the fitted model
+    /// * `parameters` - the fitted parameters
+    fn gradient_norm(
+        x: &[f64],
+        y: &[f64],
+        weights: &[f64],
+        model: &dyn RegressionModel,
+        parameters: &[f64],
+    ) -> f64 {
+        let mut gradient = vec![0.0; parameters.len()];
+        for ((x, y), weight) in x.iter().zip(y).zip(weights) {
+            let tape = Tape::new();
+            let variables: Vec<Var> = parameters
+                .iter()
+                .map(|parameter| tape.add_var(*parameter))
+                .collect();
+            let prediction = Self::model_function(model, &variables, &[&[*x]]) * weight.sqrt();
+            let residual = y * weight.sqrt() - prediction.val();
+            for (component, derivative) in
+                gradient.iter_mut().zip(prediction.grad().wrt(&variables))
+            {
+                *component += derivative * residual;
+            }
+        }
+        // Gradients that are not a number never satisfy the tolerance.
+        gradient
+            .iter()
+            .map(|component| {
+                if component.is_nan() {
+                    f64::INFINITY
+                } else {
+                    component.abs()
+                }
+            })
+            .fold(0.0, f64::max)
+    }
+
+    /// Returns the index of the observation passed to the model by the optimiser.
+    ///
+    /// # Parameters
+    ///
+    /// * `data` - the data passed to the model
+    fn observation_index(data: &[&[f64]]) -> usize {
+        data.first()
+            .and_then(|values| values.first())
+            .map(|index| *index as usize)
+            .expect("The optimiser must pass the observation index to the model.")
+    }
+
+    /// Calculates the residuals and the goodness of fit for the specified observations.
+    ///
+    /// # Parameters
+    ///
+    /// * `x` - the number of clusters of each observation
+    /// * `y` - the observed stabilities
+    /// * `weights` - the weight of each observation in the final fit
+    fn diagnose(&mut self, x: &[f64], y: &[f64], weights: &[f64]) {
+        let residuals: Vec<RegressionResidual> = x
+            .iter()
+            .zip(y)
+            .zip(weights)
+            .map(|((clusters, observed), weight)| {
+                let predicted = self.predict(*clusters);
+                RegressionResidual {
+                    clusters: *clusters,
+                    observed: *observed,
+                    predicted,
+                    residual: observed - predicted,
+                    weight: *weight,
+                }
+            })
+            .collect();
+        // All goodness of fit measures are based on the weighted sum of squared residuals,
+        // which is minimised by the fit.
+        let observations = residuals.len() as f64;
+        let total_weight: f64 = weights.iter().sum();
+        let residual_sum_of_squares: f64 = residuals
+            .iter()
+            .map(|residual| residual.weight() * residual.residual().powi(2))
+            .sum();
+        let mean = y
+            .iter()
+            .zip(weights)
+            .map(|(observed, weight)| weight * observed)
+            .sum::<f64>()
+            / total_weight;
+        let total_sum_of_squares: f64 = y
+            .iter()
+            .zip(weights)
+            .map(|(observed, weight)| weight * (observed - mean).powi(2))
+            .sum();
+        self.diagnostics.r_squared = if total_sum_of_squares > 0.0 {
+            Some(1.0 - residual_sum_of_squares / total_sum_of_squares)
+        } else {
+            None
+        };
+        self.diagnostics.root_mean_squared_error = if residuals.is_empty() || total_weight <= 0.0 {
+            None
+        } else {
+            Some((residual_sum_of_squares / total_weight).sqrt())
+        };
+        // The likelihood of a weighted least squares fit assumes normally distributed errors
+        // with variances `σ² / wᵢ`, where `σ²` is estimated as additional parameter. Up to a
+        // constant, twice the negative log-likelihood is `n ln(WRSS / n) - Σ ln wᵢ`. Robust
+        // losses do not maximise this likelihood, so their fits have no information criteria.
+        let log_weight_sum: f64 = weights.iter().map(|weight| weight.ln()).sum();
+        if !self.loss.is_robust()
+            && !residuals.is_empty()
+            && residual_sum_of_squares > 0.0
+            && log_weight_sum.is_finite()
+        {
+            let log_likelihood_term =
+                observations * (residual_sum_of_squares / observations).ln() - log_weight_sum;
+            let estimated_parameters = (self.model.number_of_parameters() + 1) as f64;
+            let aic = log_likelihood_term + 2.0 * estimated_parameters;
+            self.diagnostics.aic = Some(aic);
+            self.diagnostics.aicc = if observations - estimated_parameters - 1.0 > 0.0 {
+                Some(
+                    aic + 2.0 * estimated_parameters * (estimated_parameters + 1.0)
+                        / (observations - estimated_parameters - 1.0),
+                )
+            } else {
+                None
+            };
+            self.diagnostics.bic =
+                Some(log_likelihood_term + estimated_parameters * observations.ln());
+        }
+        self.diagnostics.residuals = residuals;
+    }
+
+    /// The regression / model function to optimise.
+    fn model_function<'a>(
+        model: &dyn RegressionModel,
+        params: &[Var<'a>],
+        data: &[&[f64]],
+    ) -> Var<'a> {
+        if params.len() != model.number_of_parameters() {
+            panic!(
+                "The number of parameters is incorrect. {} parameters were supplied.",
+                params.len()
+            );
+        }
+        let length_data = data.len();
+        let length_data_inner = data.first().map(|value| value.len());
+        if length_data != 1 || length_data_inner.map(|length| length != 1).unwrap_or(true) {
+            panic!(
+                "The input dataformat is incorrect. Format {}/{:?} were supplied.",
+                length_data, length_data_inner
+            );
+        }
+        model.evaluate(params, data[0][0])
+    }
+
+    /// Evaluates the model with the specified parameters for the number of clusters.
+    ///
+    /// # Parameters
+    ///
+    /// * `model` - the model to evaluate
+    /// * `parameters` - the parameters of the model
+    /// * `x` - the number of clusters
+    fn evaluate(model: &dyn RegressionModel, parameters: &[f64], x: f64) -> f64 {
+        let tape = Tape::new();
+        let parameters: Vec<Var> = parameters
+            .iter()
+            .map(|value| tape.add_var(*value))
+            .collect();
+        Self::model_function(model, &parameters, &[&[x]]).val()
+    }
+}
+
+impl StabilityCurve for ClusterStabilityRegression {
+    fn predict(&self, x: f64) -> f64 {
+        Self::evaluate(self.model.as_ref(), &self.parameters, x)
+    }
+}
+
+/// The stored form of a regression model, which records if the model is predefined,
+/// so that formulas named like predefined models are restored as formulas.
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum SavedModel {
+    /// A predefined model stored by its name.
+    Named { name: String },
+    /// A user-defined model stored by its formula.
+    Formula { formula: String },
+}
+
+/// Serialises a regression model by its name or, for user-defined models, its formula.
+///
+/// # Parameters
+///
+/// * `model` - the model to serialise
+/// * `serializer` - the serialiser
+fn serialize_model<M: AsRef<dyn RegressionModel>, S: Serializer>(
+    model: &M,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let model = model.as_ref();
+    match model.formula() {
+        Some(formula) => SavedModel::Formula { formula },
+        None => SavedModel::Named { name: model.name() },
+    }
+    .serialize(serializer)
+}
+
+/// Deserialises a regression model from its name or, for user-defined models, its formula.
+///
+/// # Parameters
+///
+/// * `deserializer` - the deserialiser
+fn deserialize_model<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Box<dyn RegressionModel>, D::Error> {
+    match SavedModel::deserialize(deserializer)? {
+        SavedModel::Named { name } => RegressionModelType::from_str(&name, false)
+            .map(|model_type| model_type.model())
+            .map_err(|_| D::Error::custom(format!("The regression model {} is unknown.", name))),
+        SavedModel::Formula { formula } => FormulaModel::parse(&formula)
+            .map(|formula| Box::new(formula) as Box<dyn RegressionModel>)
+            .map_err(D::Error::custom),
+    }
+}
+
+/// The information criteria used to select regression models.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InformationCriterion {
+    /// The Akaike information criterion.
+    Aic,
+    /// The Akaike information criterion corrected for small samples.
+    Aicc,
+    /// The Bayesian information criterion.
+    Bic,
+}
+
+impl InformationCriterion {
+    /// Returns the value of the criterion for the specified regression diagnostics.
+    ///
+    /// # Parameters
+    ///
+    /// * `diagnostics` - the diagnostics of a regression
+    pub fn score(&self, diagnostics: &RegressionDiagnostics) -> Option<f64> {
+        match self {
+            InformationCriterion::Aic => diagnostics.aic(),
+            InformationCriterion::Aicc => diagnostics.aicc(),
+            InformationCriterion::Bic => diagnostics.bic(),
+        }
+    }
+}
+
+#[derive(CopyGetters, Clone, Debug, PartialEq, Serialize)]
+/// The scores of a single candidate model.
+pub struct ModelCandidate {
+    /// The model.
+    #[getset(get_copy = "pub")]
+    model: RegressionModelType,
+    /// The Akaike information criterion or `None` if it cannot be determined.
+    #[getset(get_copy = "pub")]
+    aic: Option<f64>,
+    /// The Akaike information criterion corrected for small samples or `None`
+    /// if there are too few observations.
+    #[getset(get_copy = "pub")]
+    aicc: Option<f64>,
+    /// The Bayesian information criterion or `None` if it cannot be determined.
+    #[getset(get_copy = "pub")]
+    bic: Option<f64>,
+    /// The relative support of the model among all eligible candidates according to the
+    /// selection criterion or `None` if the model is not eligible or the criterion cannot be
+    /// determined.
+    #[getset(get_copy = "pub")]
+    weight: Option<f64>,
+    /// If the optimiser converged.
+    #[getset(get_copy = "pub")]
+    converged: bool,
+    /// If the fitted curve is singular within the observed range of clusters.
+    #[getset(get_copy = "pub")]
+    singular: bool,
+}
+
+impl ModelCandidate {
+    /// Returns `true` if the model can be selected, which requires a converged fit
+    /// without a singularity in the observed range of clusters.
+    pub fn is_eligible(&self) -> bool {
+        self.converged && !self.singular
+    }
+}
+
+#[derive(CopyGetters, Getters, Clone, Debug, PartialEq, Serialize)]
+/// The selection of the best supported regression model.
+pub struct ModelSelection {
+    /// The criterion used to select the model.
+    #[getset(get_copy = "pub")]
+    criterion: InformationCriterion,
+    /// The selected model or `None` if no model can be selected.
+    #[getset(get_copy = "pub")]
+    selected_model: Option<RegressionModelType>,
+    /// The reason why no model can be selected or `None` if a model is selected.
+    #[getset(get = "pub")]
+    reason: Option<String>,
+    /// The scores of all candidate models.
+    #[getset(get = "pub")]
+    candidates: Vec<ModelCandidate>,
+}
+
+/// Fits all regression models to the observations and returns the regression of the model with
+/// the lowest value of the specified information criterion. Models with too many parameters
+/// for the number of observations are not considered. Only models, whose fit converged without
+/// a singularity in the observed range of clusters, are eligible for selection. If the criterion
+/// cannot be determined for any eligible model, no model is selected and the observed
+/// stabilities are interpolated instead, while the reason is recorded in the [`ModelSelection`].
+/// Returns `None` if no model can be fitted.
+/// As the information criteria are only determined for the squared error loss, models fitted
+/// with a robust loss cannot be compared.
+///
+/// # Parameters
+///
+/// * `observations` - the observed stabilities to fit the models to
+/// * `criterion` - the information criterion used to select the model
+/// * `settings` - the settings used to fit the models
+pub fn select_model(
+    observations: &StabilityObservations,
+    criterion: InformationCriterion,
+    settings: &RegressionSettings,
+) -> Option<(StabilityFit, ModelSelection)> {
+    let mut regressions: Vec<(RegressionModelType, ClusterStabilityRegression)> =
+        RegressionModelType::value_variants()
+            .iter()
+            .filter(|model_type| {
+                ClusterStabilityRegression::is_determined(observations, model_type.model().as_ref())
+            })
+            .map(|model_type| {
+                (
+                    *model_type,
+                    ClusterStabilityRegression::new(observations, model_type.model(), settings),
+                )
+            })
+            .collect();
+    if regressions.is_empty() {
+        return None;
+    }
+    let mut candidates: Vec<ModelCandidate> = regressions
+        .iter()
+        .map(|(model_type, regression)| ModelCandidate {
+            model: *model_type,
+            aic: regression.diagnostics().aic(),
+            aicc: regression.diagnostics().aicc(),
+            bic: regression.diagnostics().bic(),
+            weight: None,
+            converged: regression.diagnostics().converged(),
+            singular: regression.diagnostics().singular(),
+        })
+        .collect();
+    let scores: Vec<Option<f64>> = regressions
+        .iter()
+        .zip(&candidates)
+        .map(|((_, regression), candidate)| {
+            criterion
+                .score(regression.diagnostics())
+                .filter(|_| candidate.is_eligible())
+        })
+        .collect();
+    let minimal_score = scores.iter().flatten().copied().reduce(f64::min);
+    // Akaike weights are the likelihoods of the models relative to the best model.
+    let relative_likelihoods: Vec<Option<f64>> = scores
+        .iter()
+        .map(|score| {
+            score
+                .zip(minimal_score)
+                .map(|(score, minimal_score)| (-(score - minimal_score) / 2.0).exp())
+        })
+        .collect();
+    let total_likelihood: f64 = relative_likelihoods.iter().flatten().sum();
+    for (candidate, relative_likelihood) in candidates.iter_mut().zip(relative_likelihoods) {
+        candidate.weight = relative_likelihood.map(|likelihood| likelihood / total_likelihood);
+    }
+    let selected_index = scores
+        .iter()
+        .position(|score| score.is_some() && *score == minimal_score);
+    let Some(selected_index) = selected_index else {
+        let reason = if candidates.iter().any(ModelCandidate::is_eligible) {
+            "The information criterion cannot be determined for any regression model that \
+             converged without a singularity in the observed range."
+        } else {
+            "No regression model converged without a singularity in the observed range."
+        };
+        return Some((
+            StabilityFit::Interpolated(InterpolatedStabilities::new(
+                observations,
+                reason.to_string(),
+            )),
+            ModelSelection {
+                criterion,
+                selected_model: None,
+                reason: Some(reason.to_string()),
+                candidates,
+            },
+        ));
+    };
+    let (selected_model, regression) = regressions.swap_remove(selected_index);
+    Some((
+        StabilityFit::Regression(regression),
+        ModelSelection {
+            criterion,
+            selected_model: Some(selected_model),
+            reason: None,
+            candidates,
+        },
+    ))
+}
+
+/// The methods to fit a curve to the stabilities of a branch.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FitMethod {
+    /// A parametric regression model.
+    #[default]
+    Regression,
+    /// A monotonically decreasing curve fitted by the pool adjacent violators algorithm.
+    Isotonic,
+    /// A locally weighted linear regression (LOESS).
+    Loess,
+}
+
+impl FitMethod {
+    /// Fits a curve to the observed stabilities. If there are no observations or fewer
+    /// observations than a regression model has parameters plus one, the observed stabilities
+    /// are interpolated instead.
+    ///
+    /// # Parameters
+    ///
+    /// * `observations` - the observed stabilities to fit the curve to
+    /// * `model` - the model fitted by a regression
+    /// * `settings` - the settings used to fit a regression
+    pub fn fit(
+        &self,
+        observations: &StabilityObservations,
+        model: &ModelSpecification,
+        settings: &RegressionSettings,
+    ) -> StabilityFit {
+        if observations.is_empty() {
+            return StabilityFit::Interpolated(InterpolatedStabilities::new(
+                observations,
+                "The branch has no transitions to fit a curve to.".to_string(),
+            ));
+        }
+        match self {
+            FitMethod::Regression => {
+                let model = model.model();
+                if ClusterStabilityRegression::is_determined(observations, model.as_ref()) {
+                    StabilityFit::Regression(ClusterStabilityRegression::new(
+                        observations,
+                        model,
+                        settings,
+                    ))
+                } else {
+                    StabilityFit::Interpolated(InterpolatedStabilities::new(
+                        observations,
+                        format!(
+                            "The branch has only {} transitions, which are too few to fit the {} \
+                             model with {} parameters.",
+                            observations.len(),
+                            model.name(),
+                            model.number_of_parameters()
+                        ),
+                    ))
+                }
+            },
+            FitMethod::Isotonic => StabilityFit::Isotonic(IsotonicRegression::new(observations)),
+            FitMethod::Loess => {
+                StabilityFit::Loess(LoessRegression::new(observations, settings.loess_span()))
+            },
+        }
+    }
+}
+
+/// A curve fitted to the stabilities of a branch.
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "fit", rename_all = "kebab-case")]
+pub enum StabilityFit {
+    /// A parametric regression.
+    Regression(ClusterStabilityRegression),
+    /// An isotonic regression.
+    Isotonic(IsotonicRegression),
+    /// A locally weighted regression.
+    Loess(LoessRegression),
+    /// An interpolation of the observed stabilities used if a branch is too short to fit.
+    Interpolated(InterpolatedStabilities),
+}
+
+impl StabilityFit {
+    /// Returns a description of why the fit is poor or `None` if the fit is acceptable.
+    pub fn fit_warning(&self) -> Option<String> {
+        match self {
+            StabilityFit::Regression(regression) => regression.diagnostics().fit_warning(),
+            StabilityFit::Isotonic(_) | StabilityFit::Loess(_) => None,
+            StabilityFit::Interpolated(interpolation) if interpolation.clusters().is_empty() => {
+                Some(format!("{} No optimal clustering can be selected.", interpolation.reason()))
+            },
+            StabilityFit::Interpolated(interpolation) => Some(format!(
+                "{} The optimal clustering is selected from the interpolated observed stabilities \
+                 instead.",
+                interpolation.reason()
+            )),
+        }
+    }
+}
+
+impl StabilityCurve for StabilityFit {
+    fn predict(&self, x: f64) -> f64 {
+        match self {
+            StabilityFit::Regression(regression) => regression.predict(x),
+            StabilityFit::Isotonic(regression) => regression.predict(x),
+            StabilityFit::Loess(regression) => regression.predict(x),
+            StabilityFit::Interpolated(interpolation) => interpolation.predict(x),
+        }
+    }
+}
+
+#[derive(CopyGetters, Clone, Copy, Debug, PartialEq, Serialize)]
+/// The stability predicted by a curve for a number of clusters.
+pub struct StabilityPrediction {
+    /// The number of clusters.
+    #[getset(get_copy = "pub")]
+    clusters: f64,
+    /// The predicted stability.
+    #[getset(get_copy = "pub")]
+    stability: f64,
+}
+
+/// Returns the stabilities predicted by the curve for the specified numbers of clusters.
 ///
 /// # Parameters
 ///
-/// * `cluster_parent` - the parent cluster to use as reference
-/// * `cluster_child` - the child cluster calculate the stability from
-pub fn cluster_overlap_relative<A: Borrow<HashSet<usize>>, B: Borrow<HashSet<usize>>>(
-    cluster_parent: A,
-    cluster_child: B,
-) -> Result<f64, String> {
-    if cluster_child.borrow().is_empty() {
-        Err("The child cluster is empty.".to_string())
-    } else {
-        let overlap = cluster_overlap_absolute(cluster_parent.borrow(), cluster_child.borrow());
-        Ok((overlap as f64) / (cluster_child.borrow().len() as f64))
+/// * `curve` - the curve to evaluate
+/// * `clusters` - the numbers of clusters
+pub fn predict_stabilities(
+    curve: &dyn StabilityCurve,
+    clusters: &[f64],
+) -> Vec<StabilityPrediction> {
+    clusters
+        .iter()
+        .map(|clusters| StabilityPrediction {
+            clusters: *clusters,
+            stability: curve.predict(*clusters),
+        })
+        .collect()
+}
+
+/// A monotonically decreasing regression of cluster stability data. The stability is
+/// interpolated linearly between the fitted values and held constant beyond them.
+#[derive(CopyGetters, Getters, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct IsotonicRegression {
+    /// The weighting of the observations.
+    #[getset(get_copy = "pub")]
+    weighting: ObservationWeighting,
+    /// The distinct numbers of clusters of the observations in ascending order.
+    #[getset(get = "pub")]
+    clusters: Vec<f64>,
+    /// The fitted stability for each distinct number of clusters.
+    #[getset(get = "pub")]
+    fitted: Vec<f64>,
+    /// The residuals of all observations.
+    #[getset(get = "pub")]
+    residuals: Vec<RegressionResidual>,
+}
+
+impl IsotonicRegression {
+    /// Fits a monotonically decreasing curve to the observed stabilities.
+    ///
+    /// # Parameters
+    ///
+    /// * `observations` - the observed stabilities to fit the curve to
+    pub fn new(observations: &StabilityObservations) -> Self {
+        let (clusters, fitted) = pool_adjacent_violators(
+            observations.clusters(),
+            observations.stabilities(),
+            observations.weights(),
+        );
+        let mut regression = Self {
+            weighting: observations.weighting(),
+            clusters,
+            fitted,
+            residuals: Vec::new(),
+        };
+        regression.residuals = observations
+            .clusters()
+            .iter()
+            .zip(observations.stabilities())
+            .zip(observations.weights())
+            .map(|((clusters, observed), weight)| {
+                let predicted = regression.predict(*clusters);
+                RegressionResidual {
+                    clusters: *clusters,
+                    observed: *observed,
+                    predicted,
+                    residual: observed - predicted,
+                    weight: *weight,
+                }
+            })
+            .collect();
+        regression
     }
 }
 
-/// Returns the relative overlaps of the child cluster with any of to the parent clusters.
-/// Returns an error if the child cluster is empty.
+impl StabilityCurve for IsotonicRegression {
+    fn predict(&self, x: f64) -> f64 {
+        interpolate(&self.clusters, &self.fitted, x)
+    }
+}
+
+/// The observed stabilities of a branch, which are interpolated linearly between the
+/// observations and held constant beyond them. Observations with the same number of
+/// clusters are averaged. Thresholding the interpolation is equivalent to thresholding
+/// the observed stabilities.
+#[derive(CopyGetters, Getters, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct InterpolatedStabilities {
+    /// The weighting of the observations.
+    #[getset(get_copy = "pub")]
+    weighting: ObservationWeighting,
+    /// The distinct numbers of clusters of the observations in ascending order.
+    #[getset(get = "pub")]
+    clusters: Vec<f64>,
+    /// The weighted mean stability for each distinct number of clusters.
+    #[getset(get = "pub")]
+    stabilities: Vec<f64>,
+    /// The reason why the observed stabilities are interpolated instead of fitted.
+    #[getset(get = "pub")]
+    reason: String,
+}
+
+impl InterpolatedStabilities {
+    /// Creates an interpolation of the observed stabilities.
+    ///
+    /// # Parameters
+    ///
+    /// * `observations` - the observed stabilities
+    /// * `reason` - the reason why the observed stabilities are interpolated
+    pub fn new(observations: &StabilityObservations, reason: String) -> Self {
+        let mut sorted: Vec<(f64, f64, f64)> = observations
+            .clusters()
+            .iter()
+            .zip(observations.stabilities())
+            .zip(observations.weights())
+            .map(|((x, y), weight)| (*x, *y, *weight))
+            .collect();
+        sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut clusters: Vec<f64> = Vec::new();
+        let mut stabilities: Vec<f64> = Vec::new();
+        for group in sorted.chunk_by(|a, b| a.0 == b.0) {
+            let total_weight: f64 = group.iter().map(|(_, _, weight)| weight).sum();
+            let stability = if total_weight > 0.0 {
+                group.iter().map(|(_, y, weight)| y * weight).sum::<f64>() / total_weight
+            } else {
+                group.iter().map(|(_, y, _)| y).sum::<f64>() / group.len() as f64
+            };
+            clusters.push(group[0].0);
+            stabilities.push(stability);
+        }
+        Self {
+            weighting: observations.weighting(),
+            clusters,
+            stabilities,
+            reason,
+        }
+    }
+}
+
+impl StabilityCurve for InterpolatedStabilities {
+    fn predict(&self, x: f64) -> f64 {
+        interpolate(&self.clusters, &self.stabilities, x)
+    }
+}
+
+/// Returns the value at `x` linearly interpolated between the specified points, which is
+/// held constant beyond the first and last point. Returns `NaN` if there are no points.
 ///
 /// # Parameters
 ///
-/// * `cluster_parent` - the parent cluster to use as reference
-/// * `cluster_child` - the child cluster calculate the stability from
-pub fn cluster_overlaps_relative<A: Borrow<HashSet<usize>>, B: Borrow<HashSet<usize>>>(
-    clusters_parent: &[A],
-    cluster_child: B,
-) -> Result<Vec<f64>, &'static str> {
-    if cluster_child.borrow().is_empty() {
-        Err("The child cluster is empty.")
+/// * `points_x` - the positions of the points in ascending order
+/// * `points_y` - the values of the points
+/// * `x` - the position to interpolate at
+fn interpolate(points_x: &[f64], points_y: &[f64], x: f64) -> f64 {
+    let upper = points_x.partition_point(|point_x| *point_x < x);
+    if upper == 0 {
+        points_y.first().copied().unwrap_or(f64::NAN)
+    } else if upper == points_x.len() {
+        points_y[upper - 1]
     } else {
-        let overlaps = clusters_parent
+        let (x_lower, x_upper) = (points_x[upper - 1], points_x[upper]);
+        let (y_lower, y_upper) = (points_y[upper - 1], points_y[upper]);
+        y_lower + (y_upper - y_lower) * (x - x_lower) / (x_upper - x_lower)
+    }
+}
+
+/// A locally weighted linear regression (LOESS) of cluster stability data.
+/// The stability at a number of clusters is predicted by a weighted linear fit to the
+/// nearest observations, which are weighted by the tricube of their relative distance.
+#[derive(CopyGetters, Getters, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct LoessRegression {
+    /// The weighting of the observations.
+    #[getset(get_copy = "pub")]
+    weighting: ObservationWeighting,
+    /// The fraction of observations used for each local fit.
+    #[getset(get_copy = "pub")]
+    span: f64,
+    /// The residuals of all observations, which also hold the observations the local
+    /// fits are calculated from.
+    #[getset(get = "pub")]
+    residuals: Vec<RegressionResidual>,
+}
+
+impl LoessRegression {
+    /// Fits a LOESS curve to the observed stabilities.
+    ///
+    /// # Parameters
+    ///
+    /// * `observations` - the observed stabilities to fit the curve to
+    /// * `span` - the fraction of observations used for each local fit
+    pub fn new(observations: &StabilityObservations, span: f64) -> Self {
+        let mut regression = Self {
+            weighting: observations.weighting(),
+            span,
+            residuals: observations
+                .clusters()
+                .iter()
+                .zip(observations.stabilities())
+                .zip(observations.weights())
+                .map(|((clusters, observed), weight)| RegressionResidual {
+                    clusters: *clusters,
+                    observed: *observed,
+                    predicted: f64::NAN,
+                    residual: f64::NAN,
+                    weight: *weight,
+                })
+                .collect(),
+        };
+        let predictions: Vec<f64> = regression
+            .residuals
             .iter()
-            .map(|cluster_parent| {
-                cluster_overlap_relative(cluster_parent.borrow(), cluster_child.borrow())
-                    .expect("The child cluster cannot be empty as this has been verified before.")
-            })
+            .map(|residual| regression.predict(residual.clusters))
             .collect();
-        Ok(overlaps)
+        for (residual, predicted) in regression.residuals.iter_mut().zip(predictions) {
+            residual.predicted = predicted;
+            residual.residual = residual.observed - predicted;
+        }
+        regression
     }
 }
 
-/// Returns the stability of the child cluster compared to the parent clusters.
-/// Returns an error if the child cluster is empty.
-/// The stability is defined as the sum of the squared relative overlaps.
+impl StabilityCurve for LoessRegression {
+    fn predict(&self, x: f64) -> f64 {
+        let mut distances: Vec<f64> = self
+            .residuals
+            .iter()
+            .map(|residual| (residual.clusters - x).abs())
+            .collect();
+        distances.sort_by(|a, b| a.total_cmp(b));
+        let neighbours =
+            ((self.span * distances.len() as f64).ceil() as usize).clamp(1, distances.len().max(1));
+        let Some(mut bandwidth) = distances.get(neighbours - 1).copied() else {
+            return f64::NAN;
+        };
+        // Spans beyond all observations widen the neighbourhood proportionally.
+        if self.span > 1.0 {
+            bandwidth *= self.span;
+        }
+        let (mut sum_w, mut sum_wx, mut sum_wy, mut sum_wxx, mut sum_wxy) =
+            (0.0, 0.0, 0.0, 0.0, 0.0);
+        for residual in &self.residuals {
+            let distance = (residual.clusters - x).abs();
+            let tricube = if bandwidth > 0.0 {
+                (1.0 - (distance / bandwidth).powi(3)).max(0.0).powi(3)
+            } else if distance == 0.0 {
+                1.0
+            } else {
+                0.0
+            };
+            let weight = tricube * residual.weight;
+            // The local fit is centred at the predicted number of clusters.
+            let centred_x = residual.clusters - x;
+            sum_w += weight;
+            sum_wx += weight * centred_x;
+            sum_wy += weight * residual.observed;
+            sum_wxx += weight * centred_x.powi(2);
+            sum_wxy += weight * centred_x * residual.observed;
+        }
+        if sum_w <= 0.0 {
+            return f64::NAN;
+        }
+        let determinant = sum_w * sum_wxx - sum_wx.powi(2);
+        // Neighbourhoods without spread in the number of clusters fall back to the local mean.
+        if determinant <= f64::EPSILON * sum_w * sum_wxx {
+            return sum_wy / sum_w;
+        }
+        // The intercept of the centred fit is the prediction.
+        (sum_wxx * sum_wy - sum_wx * sum_wxy) / determinant
+    }
+}
+
+/// Returns the distinct values of `x` in ascending order and the weighted least squares fit
+/// of `y`, which does not increase with `x`, as calculated by the pool adjacent violators
+/// algorithm. Observations with the same value of `x` are pooled first.
 ///
 /// # Parameters
 ///
-/// * `cluster_parent` - the parent cluster to use as reference
-/// * `cluster_child` - the child cluster calculate the stability from
-pub fn cluster_stability<A: Borrow<HashSet<usize>>, B: Borrow<HashSet<usize>>>(
-    clusters_parent: &[A],
-    cluster_child: B,
-) -> Result<f64, String> {
-    let relative_overlaps = cluster_overlaps_relative(clusters_parent, cluster_child)?;
-    Ok(relative_overlaps
-        .into_iter()
-        .map(|overlap| overlap.powi(2))
-        .sum())
+/// * `x` - the independent variable
+/// * `y` - the observations
+/// * `weights` - the weight of each observation
+fn pool_adjacent_violators(x: &[f64], y: &[f64], weights: &[f64]) -> (Vec<f64>, Vec<f64>) {
+    let mut observations: Vec<(f64, f64, f64)> = x
+        .iter()
+        .zip(y)
+        .zip(weights)
+        .map(|((x, y), weight)| (*x, *y, *weight))
+        .collect();
+    observations.sort_by(|a, b| a.0.total_cmp(&b.0));
+    // Each block holds the weighted sum of observations, the total weight and the number of
+    // distinct values of `x` it covers.
+    let mut distinct_x: Vec<f64> = Vec::new();
+    let mut blocks: Vec<(f64, f64, usize)> = Vec::new();
+    for (x, y, weight) in observations {
+        if distinct_x.last() == Some(&x) {
+            let block = blocks
+                .last_mut()
+                .expect("Every value of x belongs to a block.");
+            block.0 += weight * y;
+            block.1 += weight;
+        } else {
+            distinct_x.push(x);
+            blocks.push((weight * y, weight, 1));
+        }
+        // Adjacent blocks violating the order are pooled until the means decrease.
+        while blocks.len() > 1 {
+            let (sum, weight, length) = blocks[blocks.len() - 1];
+            let previous = &blocks[blocks.len() - 2];
+            if previous.0 * weight >= sum * previous.1 {
+                break;
+            }
+            blocks.pop();
+            let previous = blocks.last_mut().expect("At least one block remains.");
+            previous.0 += sum;
+            previous.1 += weight;
+            previous.2 += length;
+        }
+    }
+    let fitted = blocks
+        .iter()
+        .flat_map(|(sum, weight, length)| std::iter::repeat_n(sum / weight, *length))
+        .collect();
+    (distinct_x, fitted)
 }
 
-/// A regression of cluster stability data.
-pub struct ClusterStabilityRegression {
-    parameters: [f64; 4],
-}
+#[cfg(test)]
+mod tests {
+    use approx::{assert_abs_diff_eq, assert_ulps_eq};
 
-impl ClusterStabilityRegression {
-    pub fn new(branch: &[Rc<ResolutionNode>]) -> Self {
-        Self {
-            parameters: Self::estimate_parameters(branch),
+    use super::*;
+    use crate::{
+        data::CellSample,
+        graph::{to_graph, top_branch},
+        metric::SquaredOverlapMetric,
+    };
+
+    /// The stability depending on the number of clusters.
+    type Curve = fn(f64) -> f64;
+
+    /// Returns a branch from 2 to 30 clusters, whose stabilities follow the specified curve.
+    ///
+    /// # Parameters
+    ///
+    /// * `curve` - the stability depending on the number of clusters
+    fn synthetic_branch<F: Fn(f64) -> f64>(curve: F) -> Vec<Rc<ResolutionNode>> {
+        let mut branch = vec![Rc::new(ResolutionNode::new(0.0, 1))];
+        for clusters in 2..=30 {
+            let parent = branch.last().unwrap();
+            let node = ResolutionNode::new_with_parent(
+                clusters as f64 / 10.0,
+                clusters,
+                parent,
+                curve(clusters as f64),
+            );
+            branch.push(Rc::new(node));
+        }
+        branch
+    }
+
+    #[test]
+    fn test_regression_models() {
+        let curves: [(RegressionModelType, Curve); 4] = [
+            (RegressionModelType::Hyperbola, |x| 0.8 / (x * 0.5 - 0.2) + 0.6),
+            (RegressionModelType::ExponentialDecay, |x| 0.5 * (-0.2 * x).exp() + 0.5),
+            (RegressionModelType::Logistic, |x| 0.4 / (1.0 + (0.5 * (x - 12.0)).exp()) + 0.55),
+            (RegressionModelType::PowerLaw, |x| 0.9 * x.powf(-0.7) + 0.4),
+        ];
+        for (model_type, curve) in curves {
+            let model = model_type.model();
+            let number_of_parameters = model.number_of_parameters();
+            let regression = ClusterStabilityRegression::new(
+                &StabilityObservations::new(&synthetic_branch(curve)),
+                model,
+                &RegressionSettings::default(),
+            );
+            assert_eq!(regression.parameters().len(), number_of_parameters);
+            let diagnostics = regression.diagnostics();
+            assert_eq!(diagnostics.standard_errors().len(), number_of_parameters);
+            assert_eq!(diagnostics.residuals().len(), 29);
+            assert!((1..=DEFAULT_MAXIMUM_ITERATIONS).contains(&diagnostics.iterations()));
+            assert_eq!(diagnostics.starts(), DEFAULT_STARTS);
+            assert!(!diagnostics.singular());
+            assert!(diagnostics.r_squared().unwrap() > 0.99);
+            assert!(diagnostics.root_mean_squared_error().unwrap() < 1e-2);
+            assert_eq!(
+                diagnostics.converged(),
+                diagnostics.iterations() < DEFAULT_MAXIMUM_ITERATIONS
+            );
+            for clusters in [2.0, 7.5, 15.0, 30.0] {
+                assert_abs_diff_eq!(regression.predict(clusters), curve(clusters), epsilon = 1e-2);
+            }
         }
     }
 
-    /// Calculates the parameter estimates based on the specified branch.
-    fn estimate_parameters(branch: &[Rc<ResolutionNode>]) -> [f64; 4] {
-        let y: Vector = branch
+    #[test]
+    fn test_select_model() {
+        let observations =
+            StabilityObservations::new(&synthetic_branch(|x| 0.5 * (-0.2 * x).exp() + 0.5));
+        let (regression, selection) =
+            select_model(&observations, InformationCriterion::Bic, &RegressionSettings::default())
+                .unwrap();
+        assert_eq!(selection.selected_model(), Some(RegressionModelType::ExponentialDecay));
+        assert!(selection.reason().is_none());
+        let StabilityFit::Regression(regression) = regression else {
+            panic!("The selected model must be fitted by regression.");
+        };
+        assert_eq!(regression.model().name(), "exponential-decay");
+        assert_eq!(selection.candidates().len(), 4);
+        let total_weight: f64 = selection
+            .candidates()
             .iter()
-            .filter_map(|node| node.optimal_stability())
-            .collect();
-        let x: Vector = branch
+            .filter_map(|candidate| candidate.weight())
+            .sum();
+        assert_abs_diff_eq!(total_weight, 1.0, epsilon = 1e-12);
+        // Only eligible candidates are weighted.
+        assert!(selection
+            .candidates()
             .iter()
-            .filter_map(|node| {
-                node.optimal_stability()
-                    .map(|_| node.number_of_clusters() as f64)
+            .all(|candidate| candidate.weight().is_some() == candidate.is_eligible()));
+        // Without a converged candidate no model is selected.
+        let settings = RegressionSettings::new(
+            LossFunction::default(),
+            DEFAULT_LOESS_SPAN,
+            1,
+            0.0,
+            DEFAULT_STEP_TOLERANCE,
+            1,
+        );
+        let (fit, selection) =
+            select_model(&observations, InformationCriterion::Bic, &settings).unwrap();
+        assert!(selection
+            .candidates()
+            .iter()
+            .all(|candidate| !candidate.is_eligible()));
+        assert!(selection.selected_model().is_none());
+        assert!(selection
+            .reason()
+            .as_ref()
+            .unwrap()
+            .contains("No regression model converged"));
+        assert!(matches!(fit, StabilityFit::Interpolated(_)));
+        assert!(fit
+            .fit_warning()
+            .unwrap()
+            .contains("No regression model converged"));
+    }
+
+    #[test]
+    fn test_robust_regression() {
+        let curve = |x: f64| 0.5 * (-0.2 * x).exp() + 0.5;
+        // A single transition at 10 clusters is far less stable than expected.
+        let observations = StabilityObservations::new(&synthetic_branch(|x| {
+            if x == 10.0 {
+                curve(x) - 0.3
+            } else {
+                curve(x)
+            }
+        }));
+        let least_squares = ClusterStabilityRegression::new(
+            &observations,
+            RegressionModelType::ExponentialDecay.model(),
+            &RegressionSettings::default(),
+        );
+        for loss in [LossFunction::Huber, LossFunction::Tukey] {
+            let robust = ClusterStabilityRegression::new(
+                &observations,
+                RegressionModelType::ExponentialDecay.model(),
+                &RegressionSettings::new(
+                    loss,
+                    DEFAULT_LOESS_SPAN,
+                    DEFAULT_MAXIMUM_ITERATIONS,
+                    DEFAULT_GRADIENT_TOLERANCE,
+                    DEFAULT_STEP_TOLERANCE,
+                    DEFAULT_STARTS,
+                ),
+            );
+            assert_eq!(robust.loss(), loss);
+            let residuals = robust.diagnostics().residuals();
+            let outlier = residuals
+                .iter()
+                .find(|residual| residual.clusters() == 10.0)
+                .unwrap();
+            assert!(outlier.weight() < 0.1);
+            assert!(residuals
+                .iter()
+                .filter(|residual| residual.clusters() != 10.0)
+                .all(|residual| residual.weight() > outlier.weight()));
+            assert!(
+                (robust.predict(10.0) - curve(10.0)).abs()
+                    < (least_squares.predict(10.0) - curve(10.0)).abs()
+            );
+            // Robust fits do not maximise the Gaussian likelihood of the information criteria.
+            assert!(robust.diagnostics().converged());
+            assert!(robust.diagnostics().aic().is_none());
+            assert!(robust.diagnostics().aicc().is_none());
+            assert!(robust.diagnostics().bic().is_none());
+        }
+        assert!(least_squares
+            .diagnostics()
+            .residuals()
+            .iter()
+            .all(|residual| residual.weight() == 1.0));
+    }
+
+    #[test]
+    fn test_observation_weighting() {
+        // Cells are split into 2, 4 and 8 clusters, where the split into 8 clusters
+        // is noisy as one cell of each cluster changes clusters.
+        let labels: Vec<Vec<usize>> = vec![
+            (0..16).map(|cell| cell / 8).collect(),
+            (0..16).map(|cell| cell / 4).collect(),
+            (0..16)
+                .map(|cell| ((cell + cell % 4 / 3) / 2) % 8)
+                .collect(),
+        ];
+        let resolutions: Vec<ResolutionData> = labels
+            .iter()
+            .enumerate()
+            .map(|(index, labels)| {
+                let cells: Vec<CellSample> = labels
+                    .iter()
+                    .enumerate()
+                    .map(|(cell_id, cluster)| CellSample::new(cell_id, *cluster))
+                    .collect();
+                ResolutionData::new(0.1 * (index + 1) as f64, &cells)
             })
             .collect();
-        // Sets up and runs the non-linear regression.
-        let lm = LM::default();
-        let (inferred_parameters, _) =
-            lm.optimize(Self::model_function, &INITIAL_PARAMETER_ESTIMATES, &[&x, &y], 50);
-        [
-            inferred_parameters[0],
-            inferred_parameters[1],
-            inferred_parameters[2],
-            inferred_parameters[3],
-        ]
+        let branch = top_branch(&to_graph(&resolutions, &SquaredOverlapMetric::default()));
+        let uniform = ObservationWeighting::Uniform
+            .observations(&branch, &resolutions)
+            .unwrap();
+        assert_eq!(uniform.weights(), &vec![1.0, 1.0]);
+        assert_eq!(uniform.weighting(), ObservationWeighting::Uniform);
+        // The transitions carry the weights of the graph edges along the branch.
+        for transition in branch_transitions(&branch, &resolutions).unwrap() {
+            let child_node = branch
+                .iter()
+                .find(|node| node.number_of_clusters() == transition.clusters_child())
+                .unwrap();
+            assert!(transition.score().is_some());
+            assert_eq!(transition.score(), child_node.optimal_stability());
+        }
+        let child_clusters = ObservationWeighting::ChildClusters
+            .observations(&branch, &resolutions)
+            .unwrap();
+        let weight_of = |observations: &StabilityObservations, clusters: f64| {
+            observations
+                .clusters()
+                .iter()
+                .position(|x| *x == clusters)
+                .map(|index| observations.weights()[index])
+                .unwrap()
+        };
+        assert_ulps_eq!(weight_of(&child_clusters, 4.0), 2.0 / 3.0);
+        assert_ulps_eq!(weight_of(&child_clusters, 8.0), 4.0 / 3.0);
+        let cells = ObservationWeighting::Cells
+            .observations(&branch, &resolutions)
+            .unwrap();
+        assert_eq!(cells.weights(), &vec![1.0, 1.0]);
+        // The clean split has no variance and therefore the larger weight.
+        let inverse_variance = ObservationWeighting::InverseVariance
+            .observations(&branch, &resolutions)
+            .unwrap();
+        assert!(weight_of(&inverse_variance, 4.0) > weight_of(&inverse_variance, 8.0));
+        assert_ulps_eq!(inverse_variance.weights().iter().sum::<f64>(), 2.0);
     }
 
-    /// The regression / model function to optimise.
-    fn model_function<'a>(params: &[Var<'a>], data: &[&[f64]]) -> Var<'a> {
-        if params.len() != 4 {
-            panic!(
-                "The number of parameters is incorrect. {} parameters were supplied.",
-                params.len()
+    #[test]
+    fn test_weighted_diagnostics() {
+        let mut observations = StabilityObservations::new(&synthetic_branch(|x| {
+            0.5 * (-0.2 * x).exp() + 0.5 + 0.01 * (2.3 * x).sin()
+        }));
+        // Every third observation counts thrice, so the weights differ from uniform ones.
+        observations.weights = (0..observations.len())
+            .map(|index| if index % 3 == 0 { 3.0 } else { 1.0 })
+            .collect();
+        let regression = ClusterStabilityRegression::new(
+            &observations,
+            RegressionModelType::ExponentialDecay.model(),
+            &RegressionSettings::default(),
+        );
+        let diagnostics = regression.diagnostics();
+        let residuals = diagnostics.residuals();
+        let weighted_residual_sum_of_squares: f64 = residuals
+            .iter()
+            .map(|residual| residual.weight() * residual.residual().powi(2))
+            .sum();
+        let n = residuals.len() as f64;
+        let log_weight_sum: f64 = observations
+            .weights()
+            .iter()
+            .map(|weight| weight.ln())
+            .sum();
+        // The AIC follows the weighted sum of squared residuals minimised by the fit.
+        assert_ulps_eq!(
+            diagnostics.aic().unwrap(),
+            n * (weighted_residual_sum_of_squares / n).ln() - log_weight_sum + 2.0 * 4.0,
+            max_ulps = 8
+        );
+        let total_weight: f64 = observations.weights().iter().sum();
+        assert_ulps_eq!(
+            diagnostics.root_mean_squared_error().unwrap(),
+            (weighted_residual_sum_of_squares / total_weight).sqrt()
+        );
+        let mean = weighted_mean(observations.stabilities(), observations.weights()).unwrap();
+        let total_sum_of_squares: f64 = observations
+            .stabilities()
+            .iter()
+            .zip(observations.weights())
+            .map(|(observed, weight)| weight * (observed - mean).powi(2))
+            .sum();
+        assert_ulps_eq!(
+            diagnostics.r_squared().unwrap(),
+            1.0 - weighted_residual_sum_of_squares / total_sum_of_squares
+        );
+    }
+
+    #[test]
+    fn test_robust_weights() {
+        let residuals = [0.01, -0.02, 0.015, -0.01, 0.02, 1.0];
+        let huber = LossFunction::Huber.robust_weights(&residuals).unwrap();
+        assert_ulps_eq!(huber[0], 1.0);
+        assert!(huber[5] < 0.1);
+        let tukey = LossFunction::Tukey.robust_weights(&residuals).unwrap();
+        assert_ulps_eq!(tukey[5], 0.0);
+        assert!(LossFunction::SquaredError
+            .robust_weights(&residuals)
+            .is_none());
+        assert!(LossFunction::Huber
+            .robust_weights(&[0.0, 0.0, 1.0])
+            .is_none());
+    }
+
+    #[test]
+    fn test_threshold_crossing() {
+        let observations =
+            StabilityObservations::new(&synthetic_branch(|x| 0.5 * (-0.2 * x).exp() + 0.5));
+        let regression = ClusterStabilityRegression::new(
+            &observations,
+            RegressionModelType::ExponentialDecay.model(),
+            &RegressionSettings::default(),
+        );
+        // The curve crosses 0.8 at `ln(0.5 / 0.3) / 0.2` clusters.
+        let expected = (0.5f64 / 0.3).ln() / 0.2;
+        let crossing = regression.threshold_crossing(0.8, 1.0, 30.0).unwrap();
+        assert_abs_diff_eq!(crossing, expected, epsilon = 1e-2);
+        assert_abs_diff_eq!(regression.predict(crossing), 0.8, epsilon = 1e-6);
+        // The curve does not cross the threshold within the range.
+        assert!(regression.threshold_crossing(0.8, 1.0, 2.0).is_none());
+        assert!(regression.threshold_crossing(0.99, 5.0, 30.0).is_none());
+    }
+
+    #[test]
+    fn test_knee() {
+        // The knee of `1 / x` on [1, 10] is closest to the chord from (1, 1) to (10, 0.1)
+        // where the slope equals the chord's slope of `-0.1`, i.e. at `sqrt(10)`.
+        let knee = knee(|x| 1.0 / x, 1.0, 10.0).unwrap();
+        assert_abs_diff_eq!(knee, 10f64.sqrt(), epsilon = 1e-2);
+        assert!(super::knee(|x| 1.0 - 0.01 * x, 1.0, 10.0).is_none());
+        assert!(super::knee(|_| 0.5, 1.0, 10.0).is_none());
+        assert!(super::knee(|x| 1.0 / x, 10.0, 1.0).is_none());
+    }
+
+    #[test]
+    fn test_maximum_curvature() {
+        // The normalised parabola `(x - 5)^2` is most curved at its vertex.
+        let curvature = maximum_curvature(|x| (x - 5.0).powi(2), 0.0, 10.0).unwrap();
+        assert_abs_diff_eq!(curvature, 5.0, epsilon = 1e-2);
+        assert!(maximum_curvature(|x| 1.0 - 0.01 * x, 1.0, 10.0).is_none());
+        assert!(maximum_curvature(|_| 0.5, 1.0, 10.0).is_none());
+    }
+
+    #[test]
+    fn test_largest_second_difference() {
+        assert_eq!(largest_second_difference(&[1.0, 0.6, 0.5, 0.45, 0.44]), Some(1));
+        assert_eq!(largest_second_difference(&[1.0, 0.9, 0.7, 0.4]), None);
+        assert_eq!(largest_second_difference(&[1.0, 0.9]), None);
+    }
+
+    #[test]
+    fn test_pool_adjacent_violators() {
+        let (x, fitted) = pool_adjacent_violators(
+            &[4.0, 1.0, 2.0, 3.0, 5.0, 5.0],
+            &[0.5, 1.0, 0.8, 0.9, 0.2, 0.4],
+            &[1.0, 1.0, 1.0, 1.0, 1.0, 1.0],
+        );
+        assert_eq!(x, vec![1.0, 2.0, 3.0, 4.0, 5.0]);
+        let expected = [1.0, 0.85, 0.85, 0.5, 0.3];
+        for (fitted, expected) in fitted.iter().zip(expected) {
+            assert_ulps_eq!(*fitted, expected);
+        }
+        // The violating pair is pooled to its weighted mean.
+        let (_, fitted) = pool_adjacent_violators(&[1.0, 2.0], &[0.5, 0.8], &[3.0, 1.0]);
+        assert_ulps_eq!(fitted[0], 0.575);
+        assert_ulps_eq!(fitted[1], 0.575);
+        assert_eq!(pool_adjacent_violators(&[], &[], &[]), (vec![], vec![]));
+    }
+
+    #[test]
+    fn test_isotonic_regression() {
+        // The stability of every fifth clustering is raised above its predecessor.
+        let curve = |x: f64| 1.0 - x / 40.0 + if x % 5.0 == 0.0 { 0.05 } else { 0.0 };
+        let regression =
+            IsotonicRegression::new(&StabilityObservations::new(&synthetic_branch(curve)));
+        assert_eq!(regression.clusters().len(), 29);
+        assert_eq!(regression.residuals().len(), 29);
+        assert!(regression
+            .fitted()
+            .windows(2)
+            .all(|pair| pair[0] >= pair[1]));
+        // Monotone parts of the curve are reproduced and the fit is interpolated in between.
+        assert_ulps_eq!(regression.predict(2.0), curve(2.0));
+        assert_abs_diff_eq!(regression.predict(2.5), 1.0 - 2.5 / 40.0, epsilon = 1e-12);
+        // Outside the observed range the fit is constant.
+        assert_ulps_eq!(regression.predict(1.0), regression.predict(2.0));
+        assert_ulps_eq!(regression.predict(40.0), regression.predict(30.0));
+        let crossing = regression.threshold_crossing(0.8, 2.0, 30.0).unwrap();
+        assert_abs_diff_eq!(regression.predict(crossing), 0.8, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn test_loess_regression() {
+        // Local linear fits reproduce a straight line exactly.
+        let line = |x: f64| 0.9 - 0.01 * x;
+        let regression =
+            LoessRegression::new(&StabilityObservations::new(&synthetic_branch(line)), 0.3);
+        for clusters in [2.0, 7.3, 15.0, 30.0, 35.0] {
+            assert_abs_diff_eq!(regression.predict(clusters), line(clusters), epsilon = 1e-12);
+        }
+        assert!(regression
+            .residuals()
+            .iter()
+            .all(|residual| residual.residual().abs() < 1e-12));
+        // Smaller spans follow a curved branch more closely.
+        let curve: Curve = |x| 0.5 * (-0.2 * x).exp() + 0.5;
+        let observations = StabilityObservations::new(&synthetic_branch(curve));
+        let error = |span| {
+            LoessRegression::new(&observations, span)
+                .residuals()
+                .iter()
+                .map(|residual| residual.residual().powi(2))
+                .sum::<f64>()
+        };
+        assert!(error(0.2) < error(1.0));
+        assert!(error(1.0) < error(3.0));
+        assert!(LoessRegression::new(&StabilityObservations::new(&[]), 0.5)
+            .predict(1.0)
+            .is_nan());
+    }
+
+    #[test]
+    fn test_short_branch() {
+        let branch = synthetic_branch(|x| 1.0 - x / 10.0);
+        let settings = RegressionSettings::default();
+        // Three transitions are too few for the hyperbola, but enough for an interpolation.
+        let observations = StabilityObservations::new(&branch[..4]);
+        assert_eq!(observations.len(), 3);
+        let fit = FitMethod::Regression.fit(
+            &observations,
+            &ModelSpecification::Named(RegressionModelType::Hyperbola),
+            &settings,
+        );
+        assert!(matches!(fit, StabilityFit::Interpolated(_)));
+        assert!(fit.fit_warning().unwrap().contains("only 3 transitions"));
+        assert_ulps_eq!(fit.predict(2.5), 0.75);
+        assert_ulps_eq!(fit.predict(1.0), 0.8);
+        assert_ulps_eq!(fit.predict(10.0), 0.6);
+        // Only models with fewer parameters than observations are candidates for selection.
+        assert!(select_model(&observations, InformationCriterion::Aic, &settings).is_none());
+        let observations = StabilityObservations::new(&branch[..5]);
+        let (_, selection) =
+            select_model(&observations, InformationCriterion::Aic, &settings).unwrap();
+        assert_eq!(selection.candidates().len(), 2);
+        // Without transitions every fit method falls back to the empty interpolation.
+        let observations = StabilityObservations::new(&branch[..1]);
+        assert!(observations.is_empty());
+        for method in FitMethod::value_variants() {
+            let fit = method.fit(
+                &observations,
+                &ModelSpecification::Named(RegressionModelType::Hyperbola),
+                &settings,
             );
+            assert!(matches!(fit, StabilityFit::Interpolated(_)));
+            assert!(fit.predict(2.0).is_nan());
+            assert!(fit.fit_warning().unwrap().contains("no transitions"));
         }
-        let length_data = data.len();
-        let length_data_inner = data.get(0).map(|value| value.len());
-        if length_data != 1 || length_data_inner.map(|length| length != 1).unwrap_or(true) {
-            panic!(
-                "The input dataformat is incorrect. Format {}/{:?} were supplied.",
-                length_data, length_data_inner
+    }
+
+    #[test]
+    fn test_saved_fit() {
+        let branch = synthetic_branch(|x| 0.5 * (-0.2 * x).exp() + 0.5);
+        let observations = StabilityObservations::new(&branch);
+        for method in FitMethod::value_variants() {
+            let fit = method.fit(
+                &observations,
+                &ModelSpecification::Named(RegressionModelType::ExponentialDecay),
+                &RegressionSettings::default(),
             );
+            let saved = serde_json::to_string(&fit).unwrap();
+            let loaded: StabilityFit = serde_json::from_str(&saved).unwrap();
+            assert_eq!(serde_json::to_string(&loaded).unwrap(), saved);
+            let clusters = [1.0, 4.5, 12.0, 40.0];
+            let predictions = predict_stabilities(&loaded, &clusters);
+            for (prediction, clusters) in predictions.iter().zip(clusters) {
+                assert_eq!(prediction.clusters(), clusters);
+                assert_ulps_eq!(prediction.stability(), fit.predict(clusters));
+            }
         }
-        (params[0] / (data[0][0] * params[1] + params[2])) + params[3]
+        // Predefined models are restored by their name, which must be known.
+        let fit = FitMethod::Regression.fit(
+            &observations,
+            &ModelSpecification::Named(RegressionModelType::ExponentialDecay),
+            &RegressionSettings::default(),
+        );
+        let saved = serde_json::to_string(&fit).unwrap();
+        assert!(saved.contains(r#""model":{"type":"named","name":"exponential-decay"}"#));
+        let unknown = saved.replace("exponential-decay", "unknown");
+        assert!(serde_json::from_str::<StabilityFit>(&unknown).is_err());
     }
 
-    /// The regession model for cluster stability. It returns the predicted stability
-    /// for the specified number of clusters.
-    ///
-    /// # Parameters
-    ///
-    /// * `parameters` - the parameters of the function
-    /// * `x` - the number of clusters
-    pub fn predict(&self, x: f64) -> f64 {
-        let tape = Tape::new();
-        let parameters: Vec<Var> = self
-            .parameters
-            .iter()
-            .map(|value| tape.add_var(*value))
-            .collect();
-        Self::model_function(&parameters, &[&[x]]).val()
+    #[test]
+    fn test_formula_regression() {
+        let branch = synthetic_branch(|x| 0.5 * (-0.2 * x).exp() + 0.5);
+        let observations = StabilityObservations::new(&branch);
+        let starting_values = [
+            ("a".to_string(), 0.4),
+            ("b".to_string(), 0.1),
+            ("c".to_string(), 0.6),
+        ];
+        let formula = FormulaModel::parse("a*exp(-b*x) + c")
+            .unwrap()
+            .with_starting_values(&starting_values)
+            .unwrap();
+        let fit = FitMethod::Regression.fit(
+            &observations,
+            &ModelSpecification::Formula(formula),
+            &RegressionSettings::default(),
+        );
+        let StabilityFit::Regression(regression) = &fit else {
+            panic!("The formula is fitted by a regression.");
+        };
+        assert_eq!(regression.model().name(), "a*exp(-b*x) + c");
+        // The fixed starting values are only fitted once.
+        assert_eq!(regression.diagnostics().starts(), 1);
+        for (parameter, expected) in regression.parameters().iter().zip([0.5, 0.2, 0.5]) {
+            assert_abs_diff_eq!(*parameter, expected, epsilon = 1e-4);
+        }
+        // Saved formula fits are restored from the formula.
+        let saved = serde_json::to_string(&fit).unwrap();
+        let loaded: StabilityFit = serde_json::from_str(&saved).unwrap();
+        assert_ulps_eq!(loaded.predict(7.5), fit.predict(7.5));
+        // A formula named like a predefined model is still restored as a formula.
+        let formula = FormulaModel::parse("hyperbola")
+            .unwrap()
+            .with_starting_values(&[("hyperbola".to_string(), 0.7)])
+            .unwrap();
+        let fit = FitMethod::Regression.fit(
+            &observations,
+            &ModelSpecification::Formula(formula),
+            &RegressionSettings::default(),
+        );
+        let saved = serde_json::to_string(&fit).unwrap();
+        assert!(saved.contains(r#""model":{"type":"formula","formula":"hyperbola"}"#));
+        let loaded: StabilityFit = serde_json::from_str(&saved).unwrap();
+        let StabilityFit::Regression(regression) = &loaded else {
+            panic!("The formula is fitted by a regression.");
+        };
+        assert_eq!(regression.model().number_of_parameters(), 1);
+        assert_ulps_eq!(loaded.predict(7.5), fit.predict(7.5));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use approx::assert_ulps_eq;
+    #[test]
+    fn test_convergence() {
+        let observations =
+            StabilityObservations::new(&synthetic_branch(|x| 0.5 * (-0.2 * x).exp() + 0.5));
+        let fit = |starting_values: [f64; 3], maximum_iterations| {
+            let starting_values: Vec<(String, f64)> = ["a", "b", "c"]
+                .iter()
+                .map(|name| name.to_string())
+                .zip(starting_values)
+                .collect();
+            let formula = FormulaModel::parse("a*exp(-b*x) + c")
+                .unwrap()
+                .with_starting_values(&starting_values)
+                .unwrap();
+            let settings = RegressionSettings::new(
+                LossFunction::SquaredError,
+                DEFAULT_LOESS_SPAN,
+                maximum_iterations,
+                DEFAULT_GRADIENT_TOLERANCE,
+                DEFAULT_STEP_TOLERANCE,
+                1,
+            );
+            ClusterStabilityRegression::new(&observations, Box::new(formula), &settings)
+        };
+        // A fit started at the exact parameters satisfies the tolerances right away.
+        let regression = fit([0.5, 0.2, 0.5], DEFAULT_MAXIMUM_ITERATIONS);
+        assert!(regression.diagnostics().converged());
+        assert_eq!(regression.diagnostics().iterations(), 0);
+        assert!(regression.diagnostics().gradient_norm() <= DEFAULT_GRADIENT_TOLERANCE);
+        // A fit from a nearby starting point needs several iterations.
+        let regression = fit([0.4, 0.1, 0.6], DEFAULT_MAXIMUM_ITERATIONS);
+        assert!(regression.diagnostics().converged());
+        assert!(regression.diagnostics().iterations() > 1);
+        // A fit stopped after a single step far from the optimum does not converge.
+        let regression = fit([0.1, 1.0, 0.1], 1);
+        assert!(!regression.diagnostics().converged());
+        assert!(regression.diagnostics().iterations() <= 1);
+        assert!(regression.diagnostics().gradient_norm() > DEFAULT_GRADIENT_TOLERANCE);
+    }
 
-    use super::*;
+    #[test]
+    fn test_fit_warning() {
+        let mut diagnostics = RegressionDiagnostics {
+            standard_errors: vec![Some(0.1)],
+            residuals: Vec::new(),
+            r_squared: Some(0.9),
+            root_mean_squared_error: Some(0.01),
+            aic: None,
+            aicc: None,
+            bic: None,
+            iterations: 5,
+            gradient_norm: 1e-8,
+            starts: 1,
+            rejected_starts: 0,
+            singular: false,
+            converged: true,
+        };
+        assert!(diagnostics.fit_warning().is_none());
+        diagnostics.r_squared = Some(0.2);
+        diagnostics.converged = false;
+        let warning = diagnostics.fit_warning().unwrap();
+        assert!(warning.contains("did not converge"));
+        assert!(warning.contains("20.0%"));
+        diagnostics.singular = true;
+        assert!(diagnostics.fit_warning().unwrap().contains("singular"));
+    }
+
+    #[test]
+    fn test_multi_start() {
+        let scales: Vec<f64> = (0..5)
+            .map(ClusterStabilityRegression::decay_scale)
+            .collect();
+        assert_eq!(scales, vec![1.0, 0.5, 2.0, 0.25, 4.0]);
+        assert_eq!(Hyperbola.singularities(&[1.0, 2.0, -5.0, 0.0]), vec![2.5]);
+        assert!(Hyperbola.singularities(&[1.0, 0.0, 1.0, 0.0]).is_empty());
+        let observations = StabilityObservations::new(&synthetic_branch(|x| 0.8 / x + 0.5));
+        let range = ObservationRange::new(observations.clusters(), observations.stabilities());
+        assert!(ClusterStabilityRegression::is_singular(
+            &Hyperbola,
+            &[1.0, 1.0, -10.0, 0.5],
+            observations.clusters(),
+            &range
+        ));
+        assert!(!ClusterStabilityRegression::is_singular(
+            &Hyperbola,
+            &[1.0, 1.0, 0.0, 0.5],
+            observations.clusters(),
+            &range
+        ));
+        // The iteration limit and number of starts are configurable.
+        let settings = RegressionSettings::new(
+            LossFunction::SquaredError,
+            DEFAULT_LOESS_SPAN,
+            2,
+            DEFAULT_GRADIENT_TOLERANCE,
+            DEFAULT_STEP_TOLERANCE,
+            3,
+        );
+        let regression =
+            ClusterStabilityRegression::new(&observations, Box::new(Hyperbola), &settings);
+        assert_eq!(regression.diagnostics().starts(), 3);
+        assert!(regression.diagnostics().iterations() <= 2);
+        // More starting points never give a worse fit.
+        let residual_sum_of_squares = |starts| {
+            let settings = RegressionSettings::new(
+                LossFunction::SquaredError,
+                DEFAULT_LOESS_SPAN,
+                DEFAULT_MAXIMUM_ITERATIONS,
+                DEFAULT_GRADIENT_TOLERANCE,
+                DEFAULT_STEP_TOLERANCE,
+                starts,
+            );
+            ClusterStabilityRegression::new(&observations, Box::new(Hyperbola), &settings)
+                .diagnostics()
+                .residuals()
+                .iter()
+                .map(|residual| residual.residual().powi(2))
+                .sum::<f64>()
+        };
+        assert!(residual_sum_of_squares(5) <= residual_sum_of_squares(1));
+    }
+
+    #[test]
+    fn test_observation_range() {
+        let range = ObservationRange::new(&[4.0, 2.0, 8.0], &[0.9, 1.0, 0.7]);
+        assert_ulps_eq!(range.x_min, 2.0);
+        assert_ulps_eq!(range.y_first, 1.0);
+        assert_ulps_eq!(range.y_last, 0.7);
+        assert_ulps_eq!(range.x_span(), 6.0);
+        let empty = ObservationRange::new(&[], &[]);
+        assert_ulps_eq!(empty.x_span(), 1.0);
+    }
 
     #[test]
     fn test_cluster_overlap_absolute_partial() {
@@ -285,6 +3123,66 @@ mod tests {
         assert_ulps_eq!(0.625, cluster_stability(&clusters_parent, cluster_child).unwrap());
     }
 
+    #[test]
+    fn test_conditional_entropy_split() {
+        let clusters_parent: Vec<HashSet<usize>> = vec![
+            HashSet::from_iter(vec![0usize, 1, 2, 3]),
+            HashSet::from_iter(vec![4usize, 5, 6, 7]),
+        ];
+        let clusters_child: Vec<HashSet<usize>> = vec![
+            HashSet::from_iter(vec![0usize, 1]),
+            HashSet::from_iter(vec![2usize, 3]),
+            HashSet::from_iter(vec![4usize, 5, 6, 7]),
+        ];
+        // Half of the cells are part of a parent that was split evenly.
+        assert_ulps_eq!(
+            0.5 * 2.0f64.ln(),
+            conditional_entropy(&clusters_child, &clusters_parent).unwrap()
+        );
+        // A clean split does not merge any parents.
+        assert_ulps_eq!(0.0, conditional_entropy(&clusters_parent, &clusters_child).unwrap());
+    }
+
+    #[test]
+    fn test_weighted_mean() {
+        assert_ulps_eq!(0.25, weighted_mean(&[0.0, 1.0], &[3.0, 1.0]).unwrap());
+        assert!(weighted_mean(&[0.0, 1.0], &[1.0]).is_err());
+        assert!(weighted_mean(&[0.0, 1.0], &[0.0, 0.0]).is_err());
+        assert!(weighted_mean(&[], &[]).is_err());
+    }
+
+    #[test]
+    fn test_median() {
+        assert_ulps_eq!(0.5, median(&[0.9, 0.1, 0.5]).unwrap());
+        assert_ulps_eq!(0.4, median(&[0.9, 0.1, 0.3, 0.5]).unwrap());
+        assert!(median(&[]).is_err());
+    }
+
+    #[test]
+    fn test_trimmed_mean() {
+        let values = [0.0, 0.5, 0.6, 0.7, 10.0];
+        assert_ulps_eq!(0.6, trimmed_mean(&values, 0.2).unwrap());
+        assert_ulps_eq!(2.36, trimmed_mean(&values, 0.0).unwrap());
+        assert!(trimmed_mean(&values, 0.5).is_err());
+        assert!(trimmed_mean(&[], 0.1).is_err());
+    }
+
+    #[test]
+    fn test_conditional_entropy_identical() {
+        let clusters: Vec<HashSet<usize>> = vec![
+            HashSet::from_iter(vec![0usize, 3, 6]),
+            HashSet::from_iter(vec![1usize, 4, 7]),
+            HashSet::from_iter(vec![2usize, 5, 8]),
+        ];
+        assert_ulps_eq!(0.0, conditional_entropy(&clusters, &clusters).unwrap());
+    }
+
+    #[test]
+    fn test_conditional_entropy_empty() {
+        let clusters: Vec<HashSet<usize>> = Vec::new();
+        assert!(conditional_entropy(&clusters, &clusters).is_err());
+    }
+
     #[test]
     fn test_cluster_stability_child_empty() {
         let clusters_parent: Vec<HashSet<usize>> = vec![
@@ -0,0 +1,36 @@
+//! This module handles writing of analysis results.
+
+use std::{io::Write, path::Path};
+
+use serde::Serialize;
+
+/// Writes the specified records as rows of a CSV file with a header.
+///
+/// # Parameters
+///
+/// * `records` - the records to write
+/// * `csv_path` - the path of the CSV file
+pub fn write_csv<S: Serialize, P: AsRef<Path>>(
+    records: &[S],
+    csv_path: P,
+) -> Result<(), Box<dyn std::error::Error>> {
+    write_csv_to(records, std::fs::File::create(csv_path.as_ref())?)
+}
+
+/// Writes the specified records as CSV rows with a header to the writer.
+///
+/// # Parameters
+///
+/// * `records` - the records to write
+/// * `writer` - the writer to write the CSV data to
+pub fn write_csv_to<S: Serialize, W: Write>(
+    records: &[S],
+    writer: W,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    for record in records {
+        csv_writer.serialize(record)?;
+    }
+    csv_writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,178 @@
+//! This module provides persistence barcodes of cluster lineages across resolutions.
+//!
+//! A lineage is born at the resolution where its cluster first appears, either at the coarsest
+//! resolution or by splitting off a parent cluster. It continues as long as the cluster has a
+//! single child cluster at the next resolution and dies at the resolution where it splits
+//! further or vanishes. Lineages that reach the finest resolution are still alive.
+
+use std::collections::HashMap;
+
+use getset::{CopyGetters, Getters};
+use serde::Serialize;
+
+use crate::genealogy::ClusterGenealogyEntry;
+
+#[derive(CopyGetters, Getters, Clone, Debug, PartialEq, Serialize)]
+/// The persistence barcode of a single cluster lineage.
+pub struct PersistenceBar {
+    /// The rank of the lineage by persistence, starting with `1` for the most persistent lineage.
+    #[getset(get_copy = "pub")]
+    rank: usize,
+    /// The resolution the lineage is born at.
+    #[getset(get_copy = "pub")]
+    birth_resolution: f64,
+    /// The number of clusters at the resolution the lineage is born at.
+    #[getset(get_copy = "pub")]
+    birth_clusters: usize,
+    /// The resolution the lineage dies at or `None` if the lineage is still alive
+    /// at the finest resolution.
+    #[getset(get_copy = "pub")]
+    death_resolution: Option<f64>,
+    /// The number of clusters at the resolution the lineage dies at or `None` if the lineage
+    /// is still alive at the finest resolution.
+    #[getset(get_copy = "pub")]
+    death_clusters: Option<usize>,
+    /// The persistence of the lineage as the difference in the number of clusters between
+    /// death and birth. Lineages that are still alive are truncated at the finest resolution.
+    #[getset(get_copy = "pub")]
+    persistence: usize,
+    /// The IDs of the clusters that form the lineage ordered from birth to death.
+    #[getset(get = "pub")]
+    cluster_ids: Vec<usize>,
+}
+
+/// Returns the persistence barcodes of all cluster lineages in the specified genealogy
+/// ranked by decreasing persistence.
+///
+/// # Parameters
+///
+/// * `genealogy` - the cluster genealogy entries of a branch
+pub fn persistence_barcodes(genealogy: &[ClusterGenealogyEntry]) -> Vec<PersistenceBar> {
+    let mut genealogy: Vec<&ClusterGenealogyEntry> = genealogy.iter().collect();
+    genealogy.sort_by_key(|entry| entry.number_of_clusters());
+    let Some(finest_entry) = genealogy.last() else {
+        return Vec::new();
+    };
+    let finest_clusters = finest_entry.number_of_clusters();
+    let mut bars = Vec::new();
+    // Lineages alive at the current entry mapped by the ID of their current cluster.
+    let mut alive: HashMap<usize, PersistenceBar> = HashMap::new();
+    for (index, entry) in genealogy.iter().enumerate() {
+        if index == 0 {
+            for node in entry.nodes() {
+                alive.insert(node.cluster_id(), new_bar(entry, node.cluster_id()));
+            }
+        }
+        let Some(next_entry) = genealogy.get(index + 1) else {
+            break;
+        };
+        let mut next_alive: HashMap<usize, PersistenceBar> = HashMap::new();
+        for node in entry.nodes() {
+            let Some(mut bar) = alive.remove(&node.cluster_id()) else {
+                continue;
+            };
+            if let [child_cluster_id] = node.child_clusters()[..] {
+                // The lineage continues.
+                bar.cluster_ids.push(child_cluster_id);
+                next_alive.insert(child_cluster_id, bar);
+            } else {
+                // The lineage splits or vanishes.
+                bar.death_resolution = Some(next_entry.resolution());
+                bar.death_clusters = Some(next_entry.number_of_clusters());
+                bar.persistence = next_entry.number_of_clusters() - bar.birth_clusters;
+                bars.push(bar);
+                for child_cluster_id in node.child_clusters() {
+                    next_alive.insert(*child_cluster_id, new_bar(next_entry, *child_cluster_id));
+                }
+            }
+        }
+        alive = next_alive;
+    }
+    bars.extend(alive.into_values().map(|mut bar| {
+        bar.persistence = finest_clusters - bar.birth_clusters;
+        bar
+    }));
+    bars.sort_by(|a, b| {
+        b.persistence()
+            .cmp(&a.persistence())
+            .then(a.birth_clusters().cmp(&b.birth_clusters()))
+            .then(a.cluster_ids().cmp(b.cluster_ids()))
+    });
+    for (index, bar) in bars.iter_mut().enumerate() {
+        bar.rank = index + 1;
+    }
+    bars
+}
+
+/// Returns a new unranked lineage born at the specified genealogy entry.
+///
+/// # Parameters
+///
+/// * `entry` - the genealogy entry the lineage is born at
+/// * `cluster_id` - the ID of the cluster that starts the lineage
+fn new_bar(entry: &ClusterGenealogyEntry, cluster_id: usize) -> PersistenceBar {
+    PersistenceBar {
+        rank: 0,
+        birth_resolution: entry.resolution(),
+        birth_clusters: entry.number_of_clusters(),
+        death_resolution: None,
+        death_clusters: None,
+        persistence: 0,
+        cluster_ids: vec![cluster_id],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{CellSample, ResolutionData};
+
+    #[test]
+    fn test_persistence_barcodes() {
+        // Cells are split into 2, 3 and 4 clusters, where the first cluster splits
+        // into two at the second resolution and the last cluster at the third.
+        let labels: [[usize; 8]; 3] = [
+            [0, 0, 0, 0, 1, 1, 1, 1],
+            [0, 0, 2, 2, 1, 1, 1, 1],
+            [0, 0, 2, 2, 1, 1, 3, 3],
+        ];
+        let resolutions: Vec<ResolutionData> = labels
+            .iter()
+            .enumerate()
+            .map(|(index, labels)| {
+                let cells: Vec<CellSample> = labels
+                    .iter()
+                    .enumerate()
+                    .map(|(cell_id, cluster)| CellSample::new(cell_id, *cluster))
+                    .collect();
+                ResolutionData::new(0.1 * (index + 1) as f64, &cells)
+            })
+            .collect();
+        let genealogy = ClusterGenealogyEntry::from_resolution_data(&resolutions).unwrap();
+        let bars = persistence_barcodes(&genealogy);
+        assert_eq!(bars.len(), 6);
+        // The second cluster persists from 2 to 4 clusters.
+        assert_eq!(bars[0].rank(), 1);
+        assert_eq!(bars[0].cluster_ids(), &vec![1, 1]);
+        assert_eq!(bars[0].birth_clusters(), 2);
+        assert_eq!(bars[0].death_clusters(), Some(4));
+        assert_eq!(bars[0].persistence(), 2);
+        // The first cluster splits at the second resolution.
+        assert_eq!(bars[1].cluster_ids(), &vec![0]);
+        assert_eq!(bars[1].death_clusters(), Some(3));
+        assert_eq!(bars[1].persistence(), 1);
+        // Both clusters originating from the first cluster are still alive.
+        assert_eq!(bars[2].cluster_ids(), &vec![0, 0]);
+        assert_eq!(bars[2].death_resolution(), None);
+        assert_eq!(bars[2].persistence(), 1);
+        assert_eq!(bars[3].cluster_ids(), &vec![2, 2]);
+        assert_eq!(bars[4].birth_clusters(), 4);
+        assert_eq!(bars[5].rank(), 6);
+        assert_eq!(bars[5].persistence(), 0);
+    }
+
+    #[test]
+    fn test_persistence_barcodes_empty() {
+        assert!(persistence_barcodes(&[]).is_empty());
+    }
+}
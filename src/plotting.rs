@@ -2,7 +2,7 @@
 
 use std::{path::Path, rc::Rc};
 
-use crate::{graph::ResolutionNode, optimisation::ClusterStabilityRegression};
+use crate::{graph::ResolutionNode, optimisation::StabilityCurve};
 
 use plotters::prelude::*;
 
@@ -13,18 +13,65 @@ const AXIS_Y_DEFAULT: f32 = 100.0;
 /// How many individual points are used to draw the regression line.
 const PLOTTING_RESOLUTION_STEPS_REGRESSION: usize = 1000;
 
+/// An additional curve plotted next to the stability of a branch.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlotCurve {
+    /// The label of the curve shown in the legend.
+    label: String,
+    /// The points of the curve as pairs of the number of clusters and the value.
+    points: Vec<(f64, f64)>,
+}
+
+impl PlotCurve {
+    /// Creates a new curve.
+    ///
+    /// # Parameters
+    ///
+    /// * `label` - the label of the curve shown in the legend
+    /// * `points` - the points of the curve as pairs of the number of clusters and the value
+    pub fn new<S: Into<String>>(label: S, points: Vec<(f64, f64)>) -> Self {
+        Self {
+            label: label.into(),
+            points,
+        }
+    }
+
+    /// Returns the points of the curve as pairs of the number of clusters and the value.
+    pub fn points(&self) -> &[(f64, f64)] {
+        &self.points
+    }
+
+    /// Returns the curve with all values multiplied by the specified factor.
+    ///
+    /// # Parameters
+    ///
+    /// * `factor` - the factor to multiply the values with
+    pub fn scaled(self, factor: f64) -> Self {
+        Self {
+            points: self
+                .points
+                .into_iter()
+                .map(|(x, y)| (x, y * factor))
+                .collect(),
+            ..self
+        }
+    }
+}
+
 /// Plots the specified branch of a stability graph as SVG.
 ///
 /// # Parameters
 ///
 /// * `branch` - the branch to plot
+/// * `regression` - the curve fitted to the branch stabilities
+/// * `additional_curves` - further curves to plot next to the stability of the branch
 /// * `plot_path` - the file path to save the plot to
 pub fn plot_branch<P: AsRef<Path>>(
     branch: &[Rc<ResolutionNode>],
+    regression: &dyn StabilityCurve,
+    additional_curves: &[PlotCurve],
     plot_path: P,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let regression = ClusterStabilityRegression::new(&branch);
-
     let max_x = branch
         .iter()
         // .flat_map(|branch| branch.iter())
@@ -33,6 +80,15 @@ pub fn plot_branch<P: AsRef<Path>>(
         .map(|clusters| clusters as f32 * AXIS_EXTENSION)
         .unwrap_or(AXIS_Y_DEFAULT * AXIS_EXTENSION);
 
+    // The stability lies within `[0, 1]`, but additional curves such as the modularity
+    // may exceed this range, so the y axis is extended to all their values.
+    let (min_y, max_y) = additional_curves
+        .iter()
+        .flat_map(|curve| curve.points.iter())
+        .map(|(_, y)| *y as f32)
+        .filter(|y| y.is_finite())
+        .fold((0.0f32, 1.0f32), |(min_y, max_y), y| (min_y.min(y), max_y.max(y)));
+
     let root = SVGBackend::new(plot_path.as_ref(), (1800, 1200)).into_drawing_area();
     root.fill(&WHITE)?;
     let mut chart = ChartBuilder::on(&root)
@@ -40,27 +96,53 @@ pub fn plot_branch<P: AsRef<Path>>(
         .margin(5)
         .x_label_area_size(30)
         .y_label_area_size(30)
-        .build_cartesian_2d(0f32..(max_x as f32), 0f32..(1.0f32 * AXIS_EXTENSION))?;
+        .build_cartesian_2d(0f32..max_x, (min_y * AXIS_EXTENSION)..(max_y * AXIS_EXTENSION))?;
 
     chart.configure_mesh().draw()?;
 
-    chart.draw_series(LineSeries::new(
-        branch
-            .iter()
-            .filter_map(|node| {
-                node.optimal_stability()
-                    .map(|s| (node.number_of_clusters(), s))
-            })
-            .map(|(n, s)| (n as f32, s as f32)),
-        &BLACK,
-    ))?;
-
-    chart.draw_series(LineSeries::new(
-        (0..=PLOTTING_RESOLUTION_STEPS_REGRESSION)
-            .map(|x| (x as f64 / PLOTTING_RESOLUTION_STEPS_REGRESSION as f64) * max_x as f64)
-            .map(|x| (x as f32, regression.predict(x) as f32)),
-        &RED,
-    ))?;
+    chart
+        .draw_series(LineSeries::new(
+            branch
+                .iter()
+                .filter_map(|node| {
+                    node.optimal_stability()
+                        .map(|s| (node.number_of_clusters(), s))
+                })
+                .map(|(n, s)| (n as f32, s as f32)),
+            &BLACK,
+        ))?
+        .label("stability")
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], BLACK));
+
+    chart
+        .draw_series(LineSeries::new(
+            (0..=PLOTTING_RESOLUTION_STEPS_REGRESSION)
+                .map(|x| (x as f64 / PLOTTING_RESOLUTION_STEPS_REGRESSION as f64) * max_x as f64)
+                .map(|x| (x as f32, regression.predict(x) as f32)),
+            &RED,
+        ))?
+        .label("regression")
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], RED));
+
+    for (index, curve) in additional_curves.iter().enumerate() {
+        // The first colours of the palette are skipped as they resemble black and red.
+        let colour = Palette99::pick(index + 2).to_rgba();
+        chart
+            .draw_series(LineSeries::new(
+                curve.points.iter().map(|(x, y)| (*x as f32, *y as f32)),
+                colour,
+            ))?
+            .label(curve.label.as_str())
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], colour));
+    }
+
+    if !additional_curves.is_empty() {
+        chart
+            .configure_series_labels()
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()?;
+    }
 
     root.present()?;
     Ok(())